@@ -18,10 +18,14 @@ fi
 
 bindgen --generate functions,types,vars \
 	--allowlist-function 'nfssvc' \
+	--allowlist-function 'sysctlbyname' \
 	--allowlist-type 'nfsstatsv1' \
+	--allowlist-var 'NFSPROC_.*' \
 	--allowlist-var 'NFSSTATS_V1' \
 	--allowlist-var 'NFSSVC_GETSTATS' \
 	--allowlist-var 'NFSSVC_NEWSTRUCT' \
+	--allowlist-var 'NFSV42_NOPS' \
+	--allowlist-var 'NFSV42_NPROCS' \
 	--allowlist-var 'NFSV4OP.*' \
 	--with-derive-default \
 	${CRATEDIR}/bindgen/wrapper.h > ${OUTFILE}
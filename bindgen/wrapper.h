@@ -1,5 +1,6 @@
 #include <sys/param.h>
 #include <sys/mount.h>
+#include <sys/sysctl.h>
 #include <sys/time.h>
 #include <nfs/nfsproto.h>
 #include <nfsclient/nfs.h>
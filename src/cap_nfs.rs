@@ -4,7 +4,10 @@ use std::{ffi::CStr, io};
 use bincode_next::config;
 use capsicum::casper::{self, NvError, NvFlag, NvList, ServiceRegisterFlags};
 
-use crate::nfs;
+use crate::{
+    nfs,
+    source::{Error, StatsSource},
+};
 
 const BINCODE_CONFIG: config::Configuration<
     config::LittleEndian,
@@ -41,22 +44,28 @@ casper::service!(
 
 impl CapNfsAgent {
     /// Retrieve NFS stats
-    pub fn nfsstat(&mut self) -> io::Result<nfs::NfsStat> {
+    pub fn nfsstat(&mut self) -> Result<nfs::NfsStat, Error> {
         let mut invl = NvList::new(NvFlag::None).unwrap();
         invl.insert_string("cmd", "nfsstat").unwrap();
         let onvl = self.xfer_nvlist(invl)?;
         match onvl.get_binary("nfsstat") {
             Ok(Some(sl)) => {
-                Ok(bincode_next::borrow_decode_from_slice(sl, BINCODE_CONFIG)
-                    .unwrap()
-                    .0)
+                bincode_next::borrow_decode_from_slice(sl, BINCODE_CONFIG)
+                    .map(|(nfsstat, _)| nfsstat)
+                    .map_err(|_| Error::BadReply("cannot decode nfsstat"))
             }
-            Ok(None) => panic!("zygote did not return the expected value"),
+            Ok(None) => Err(Error::BadReply("missing nfsstat")),
             Err(NvError::NativeError(e)) => {
-                Err(io::Error::from_raw_os_error(e))
+                Err(io::Error::from_raw_os_error(e).into())
             }
-            Err(NvError::Io(e)) => Err(e),
-            _ => unimplemented!(),
+            Err(NvError::Io(e)) => Err(e.into()),
+            _ => Err(Error::BadReply("cannot read nfsstat")),
         }
     }
 }
+
+impl StatsSource for CapNfsAgent {
+    fn nfsstat(&mut self) -> Result<nfs::NfsStat, Error> {
+        CapNfsAgent::nfsstat(self)
+    }
+}
@@ -0,0 +1,1584 @@
+// vim: tw=80
+//! Publishes NFS statistics to Prometheus.
+//!
+//! Rather than keeping a metric object for every series, the collector takes
+//! a fresh sample of the statistics on every scrape and builds the metric
+//! families from it.  That lets the kernel's counters be published as real
+//! counters, even though they can only ever be set, never incremented.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto::{Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType},
+};
+
+use crate::{
+    counters::{ResetState, WrapState},
+    nfs::{self, ClientStat, NfsStat},
+    source::StatsSource,
+};
+
+/// A statistics source, shared between the collector and the watchdog
+pub type SharedSource = Arc<Mutex<Box<dyn StatsSource>>>;
+
+/// When each counter family published by the latest scrape started counting,
+/// by name.  The classic text format has no way to express that, so it's
+/// kept alongside the registry for OpenMetrics.
+pub type Created = Arc<Mutex<BTreeMap<&'static str, SystemTime>>>;
+
+/// Which generation of metric names to publish
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Schema {
+    /// The original names
+    #[default]
+    V1,
+    /// Names that follow Prometheus's conventions, with base units
+    V2,
+    /// Both, for migrating from v1 to v2
+    Both,
+}
+
+/// What an [`NfsCollector`] publishes, and how
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Publish client statistics
+    pub client:      bool,
+    /// Publish server statistics
+    pub server:      bool,
+    /// Which generation of metric names to publish
+    pub schema:      Schema,
+    /// Also publish server statistics under node_exporter's names
+    pub node_compat: bool,
+    /// Adjust nfsd's counters to hide its restarts
+    pub monotonic:   bool,
+}
+
+/// What became of a v1 metric in the v2 schema
+#[derive(Clone, Copy, Debug)]
+enum Successor {
+    /// It's the same in both
+    Same,
+    /// It has a new name, but the same values
+    Renamed(&'static str),
+    /// It was dropped in favor of this other metric
+    Replaced(&'static str),
+}
+
+/// Definition of a metric family
+#[derive(Debug)]
+pub struct Spec {
+    /// The metric's v1 name
+    pub name:   &'static str,
+    pub help:   &'static str,
+    pub kind:   MetricType,
+    pub labels: &'static [&'static str],
+    successor:  Successor,
+}
+
+impl Spec {
+    const fn counter(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        let kind = MetricType::COUNTER;
+        let successor = Successor::Same;
+        Spec {
+            name,
+            help,
+            kind,
+            labels,
+            successor,
+        }
+    }
+
+    const fn gauge(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        let kind = MetricType::GAUGE;
+        let successor = Successor::Same;
+        Spec {
+            name,
+            help,
+            kind,
+            labels,
+            successor,
+        }
+    }
+
+    /// Publish the metric under a different name in the v2 schema
+    const fn renamed(mut self, v2: &'static str) -> Self {
+        self.successor = Successor::Renamed(v2);
+        self
+    }
+
+    /// Omit the metric from the v2 schema, in favor of another one
+    const fn replaced_by(mut self, v2: &'static str) -> Self {
+        self.successor = Successor::Replaced(v2);
+        self
+    }
+
+    /// The names and help strings that the metric is published under
+    fn names(&self, schema: Schema) -> Vec<(&'static str, String)> {
+        let help = self.help.to_string();
+        match (self.successor, schema) {
+            (Successor::Same, _) | (_, Schema::V1) => vec![(self.name, help)],
+            (Successor::Renamed(v2), Schema::V2) => vec![(v2, help)],
+            (Successor::Replaced(_), Schema::V2) => vec![],
+            (
+                Successor::Renamed(v2) | Successor::Replaced(v2),
+                Schema::Both,
+            ) => {
+                let deprecated =
+                    format!("{help} (deprecated; use {v2} instead)");
+                let mut names = vec![(self.name, deprecated)];
+                if let Successor::Renamed(_) = self.successor {
+                    names.push((v2, help));
+                }
+                names
+            }
+        }
+    }
+}
+
+// Metrics about the collection itself
+const UP: Spec = Spec::gauge(
+    "nfs_up",
+    "1 if NFS statistics were collected successfully, else 0",
+    &[],
+);
+const COLLECT_ERRORS: Spec = Spec::counter(
+    "nfs_collect_errors_total",
+    "Count of failures to collect NFS statistics, by errno number",
+    &["errno"],
+);
+const KERNEL_STRUCT_VERSION: Spec = Spec::gauge(
+    "nfs_exporter_kernel_struct_version",
+    "The revision of the kernel's nfsstatsv1 structure being read",
+    &["layout", "vers", "osreldate"],
+);
+
+// Server metrics
+const BYTES: Spec = Spec::counter(
+    "nfs_nfsd_total_bytes",
+    "Total nfsd bytes per operation",
+    &["method"],
+)
+.renamed("nfs_nfsd_bytes_total");
+const DURATION: Spec = Spec::counter(
+    "nfs_nfsd_total_duration",
+    "Total nfsd nanoseconds spent processing each operation",
+    &["method"],
+)
+.replaced_by("nfs_nfsd_op_duration_seconds_total");
+const RPCS: Spec = Spec::counter(
+    "nfs_nfsd_requests_total",
+    "Count of server RPCs",
+    &["method"],
+);
+const IN_FLIGHT: Spec = Spec::gauge(
+    "nfs_nfsd_ops_in_flight",
+    "Number of operations that nfsd has started but not yet completed",
+    &[],
+);
+const OP_BYTES: Spec = Spec::counter(
+    "nfs_nfsd_op_bytes_total",
+    "Total bytes processed by each nfsd operation",
+    &["method"],
+);
+const OP_COMPLETED: Spec = Spec::counter(
+    "nfs_nfsd_op_completed_total",
+    "Count of completed nfsd operations",
+    &["method"],
+);
+const OP_DURATION: Spec = Spec::counter(
+    "nfs_nfsd_op_duration_seconds_total",
+    "Total time nfsd spent processing each operation",
+    &["method"],
+);
+const OP_INFO: Spec = Spec::gauge(
+    "nfs_nfsd_operation_info",
+    "The NFS version that introduced each operation, and its category",
+    &["method", "version", "category"],
+);
+const STARTCNT: Spec = Spec::counter(
+    "nfs_nfsd_start_count",
+    "Total number of operations started since boot",
+    &[],
+)
+.renamed("nfs_nfsd_operations_started_total");
+const DONECNT: Spec = Spec::counter(
+    "nfs_nfsd_done_count",
+    "Total number of operations completed since boot",
+    &[],
+)
+.renamed("nfs_nfsd_operations_completed_total");
+const BUSY: Spec = Spec::gauge(
+    "nfs_nfsd_busy",
+    "1 if nfsd is currently processing at least one operation, else 0",
+    &[],
+);
+const SINCE_PROGRESS: Spec = Spec::gauge(
+    "nfs_nfsd_seconds_since_progress",
+    concat!(
+        "Seconds since nfsd last completed an operation, or since it became ",
+        "busy if it hasn't completed one since.  0 if idle."
+    ),
+    &[],
+);
+const BUSYTIME: Spec = Spec::counter(
+    "nfs_nfsd_busytime",
+    "Total time in ns that nfsd was busy with at least one operation",
+    &[],
+)
+.replaced_by("nfs_nfsd_busy_seconds_total");
+const BUSY_SECONDS: Spec = Spec::counter(
+    "nfs_nfsd_busy_seconds_total",
+    "Total time that nfsd was busy with at least one operation",
+    &[],
+);
+const COUNTER_RESETS: Spec = Spec::counter(
+    "nfs_nfsd_counter_resets_total",
+    "Number of times nfsd's counters were reset, as when nfsd restarts",
+    &[],
+);
+const LAST_RESET: Spec = Spec::gauge(
+    "nfs_nfsd_last_reset_timestamp_seconds",
+    "When the exporter last detected a reset of nfsd's counters",
+    &[],
+);
+const CACHE_INPROG: Spec = Spec::counter(
+    "nfs_nfsd_cache_in_progress_hits",
+    "Server cache in-progress hits",
+    &[],
+)
+.renamed("nfs_nfsd_cache_in_progress_hits_total");
+const CACHE_NONIDEMPOTENT: Spec = Spec::counter(
+    "nfs_nfsd_cache_nonidempotent_hits",
+    "Server cache non-idempotent hits",
+    &[],
+)
+.renamed("nfs_nfsd_cache_nonidempotent_hits_total");
+const CACHE_HITS: Spec =
+    Spec::counter("nfs_nfsd_server_cache_hits", "Server cache hits", &[])
+        .renamed("nfs_nfsd_server_cache_hits_total");
+const CACHE_MISSES: Spec =
+    Spec::counter("nfs_nfsd_server_cache_misses", "Server cache misses", &[])
+        .renamed("nfs_nfsd_server_cache_misses_total");
+const CACHE_NOCACHE: Spec = Spec::counter(
+    "nfs_nfsd_server_cache_nocache",
+    "Server requests that bypassed the cache",
+    &[],
+)
+.renamed("nfs_nfsd_server_cache_nocache_total");
+const CACHE_SIZE: Spec = Spec::gauge(
+    "nfs_nfsd_server_cache_size",
+    "Server cache size in entries",
+    &[],
+);
+const CACHE_TCPPEAK: Spec = Spec::gauge(
+    "nfs_nfsd_server_cache_tcp_peak",
+    "Peak size of the NFS server's TCP client cache",
+    &[],
+);
+const THREADS: Spec =
+    Spec::gauge("nfs_nfsd_threads", "Number of nfsd threads", &[]);
+const NET_PACKETS: Spec = Spec::counter(
+    "nfs_nfsd_packets_total",
+    "Count of network packets received by nfsd",
+    &["protocol"],
+);
+const NET_CONNECTIONS: Spec = Spec::counter(
+    "nfs_nfsd_connections_total",
+    "Count of TCP connections accepted by nfsd",
+    &[],
+);
+const RPC_CALLS: Spec = Spec::counter(
+    "nfs_nfsd_rpcs_total",
+    "Count of RPCs received by nfsd, including bad ones",
+    &[],
+);
+const RPC_ERRORS: Spec = Spec::counter(
+    "nfs_nfsd_rpc_errors_total",
+    "Count of RPCs rejected by nfsd, by reason",
+    &["error"],
+);
+const CLIENTS: Spec = Spec::gauge(
+    "nfs_nfsd_clients",
+    "Number of connected NFS v4.x clients",
+    &[],
+);
+const DELEGS: Spec = Spec::gauge(
+    "nfs_nfsd_delegations",
+    "Number of active NFS delegations",
+    &[],
+);
+const LAYOUTS: Spec = Spec::gauge(
+    "nfs_nfsd_layouts",
+    "Number of active pNFS layouts issued by the server",
+    &[],
+);
+const LOCK_OWNER: Spec = Spec::gauge(
+    "nfs_nfsd_lock_owners",
+    "Number of active NFS lock owners",
+    &[],
+);
+const LOCKS: Spec =
+    Spec::gauge("nfs_nfsd_locks", "Number of active NFS locks", &[]);
+const PNFS_MIRROR_LEVEL: Spec = Spec::gauge(
+    "nfs_nfsd_pnfs_mirror_level",
+    "Number of pNFS data servers that each file is mirrored across",
+    &[],
+);
+const OPEN_OWNER: Spec = Spec::gauge(
+    "nfs_nfsd_open_owners",
+    "Number of active NFS v4.0 Open Owners",
+    &[],
+);
+const OPENS: Spec =
+    Spec::gauge("nfs_nfsd_opens", "Number of NFS v4.x open files?", &[]);
+
+// Client metrics
+const CALLBACK_REQUESTS: Spec = Spec::counter(
+    "nfs_callback_requests_total",
+    "Count of NFS v4 callback RPCs received by the client",
+    &["method"],
+);
+const CLIENT_CACHE: Spec = Spec::counter(
+    "nfs_client_cache_requests",
+    "Client cache lookups, by cache and result",
+    &["cache", "result"],
+)
+.renamed("nfs_client_cache_requests_total");
+const CLIENT_CACHE_HIT_RATIO: Spec = Spec::gauge(
+    "nfs_client_cache_hit_ratio",
+    "Fraction of client cache lookups that hit since the previous scrape",
+    &["cache"],
+);
+const CLIENT_DELEGS: Spec = Spec::gauge(
+    "nfs_client_delegations",
+    "Number of NFS delegations held by the client",
+    &[],
+);
+const CLIENT_LAYOUTS: Spec = Spec::gauge(
+    "nfs_client_layouts",
+    "Number of pNFS layouts held by the client",
+    &[],
+);
+const CLIENT_LOCK_OWNER: Spec = Spec::gauge(
+    "nfs_client_lock_owners",
+    "Number of NFS lock owners held by the client",
+    &["scope"],
+);
+const CLIENT_LOCKS: Spec = Spec::gauge(
+    "nfs_client_locks",
+    "Number of NFS locks held by the client",
+    &["scope"],
+);
+const CLIENT_OPEN_OWNER: Spec = Spec::gauge(
+    "nfs_client_open_owners",
+    "Number of NFS v4.0 Open Owners held by the client",
+    &["scope"],
+);
+const CLIENT_OPENS: Spec = Spec::gauge(
+    "nfs_client_opens",
+    "Number of NFS v4.x open files held by the client",
+    &["scope"],
+);
+const CLIENT_PHYSIOS: Spec = Spec::counter(
+    "nfs_client_physios",
+    "Count of client I/O operations that bypassed the buffer cache",
+    &["method"],
+)
+.renamed("nfs_client_physios_total");
+const CLIENT_REQUESTS: Spec = Spec::counter(
+    "nfs_client_requests_total",
+    "Count of client RPCs",
+    &["method"],
+);
+const CLIENT_RPCS: Spec =
+    Spec::counter("nfs_client_rpcs", "Count of client RPCs", &[])
+        .renamed("nfs_client_rpcs_total");
+const CLIENT_RPC_INVALID: Spec = Spec::counter(
+    "nfs_client_rpc_invalid_replies",
+    "Count of client RPC replies that could not be parsed",
+    &[],
+)
+.renamed("nfs_client_rpc_invalid_replies_total");
+const CLIENT_RPC_RETRIES: Spec =
+    Spec::counter("nfs_client_rpc_retries", "Count of client RPC retries", &[])
+        .renamed("nfs_client_rpc_retries_total");
+const CLIENT_RPC_RETRY_RATIO: Spec = Spec::gauge(
+    "nfs_client_rpc_retry_ratio",
+    "Fraction of client RPCs retried since the previous scrape",
+    &[],
+);
+const CLIENT_RPC_TIMEOUTS: Spec = Spec::counter(
+    "nfs_client_rpc_timeouts",
+    "Count of client RPC timeouts",
+    &[],
+)
+.renamed("nfs_client_rpc_timeouts_total");
+const CLIENT_RPC_TIMEOUT_RATIO: Spec = Spec::gauge(
+    "nfs_client_rpc_timeout_ratio",
+    "Fraction of client RPCs that timed out since the previous scrape",
+    &[],
+);
+const CLIENT_RPC_UNEXPECTED: Spec = Spec::counter(
+    "nfs_client_rpc_unexpected_replies",
+    "Count of client RPC replies that matched no outstanding request",
+    &[],
+)
+.renamed("nfs_client_rpc_unexpected_replies_total");
+
+// Per-mount client metrics
+const MOUNT_LABELS: &[&str] = &["mountpoint", "server", "export", "method"];
+const MOUNT_INFO: Spec = Spec::gauge(
+    "nfs_client_mount_info",
+    "Information about each NFS mount",
+    &["mountpoint", "server", "export", "version"],
+);
+const MOUNT_BYTES: Spec = Spec::counter(
+    "nfs_client_mount_bytes_total",
+    "Bytes read and written through each NFS mount",
+    &["mountpoint", "server", "export", "method", "kind"],
+);
+const MOUNT_REQUESTS: Spec = Spec::counter(
+    "nfs_client_mount_requests_total",
+    "Count of NFS operations requested on each mount",
+    MOUNT_LABELS,
+);
+const MOUNT_TRANSMISSIONS: Spec = Spec::counter(
+    "nfs_client_mount_transmissions_total",
+    "Count of NFS operation transmissions, including retransmissions",
+    MOUNT_LABELS,
+);
+const MOUNT_MAJOR_TIMEOUTS: Spec = Spec::counter(
+    "nfs_client_mount_major_timeouts_total",
+    "Count of NFS operation major timeouts",
+    MOUNT_LABELS,
+);
+const MOUNT_SENT_BYTES: Spec = Spec::counter(
+    "nfs_client_mount_sent_bytes_total",
+    "Bytes sent for each NFS operation, including headers",
+    MOUNT_LABELS,
+);
+const MOUNT_RECEIVED_BYTES: Spec = Spec::counter(
+    "nfs_client_mount_received_bytes_total",
+    "Bytes received for each NFS operation, including headers",
+    MOUNT_LABELS,
+);
+const MOUNT_QUEUE: Spec = Spec::counter(
+    "nfs_client_mount_queue_seconds_total",
+    "Total time NFS operations spent waiting to be transmitted",
+    MOUNT_LABELS,
+);
+const MOUNT_RTT: Spec = Spec::counter(
+    "nfs_client_mount_rtt_seconds_total",
+    "Total time NFS operations spent waiting for the server's reply",
+    MOUNT_LABELS,
+);
+const MOUNT_EXECUTE: Spec = Spec::counter(
+    "nfs_client_mount_execute_seconds_total",
+    "Total time from NFS operations' request to completion",
+    MOUNT_LABELS,
+);
+const MOUNT_ERRORS: Spec = Spec::counter(
+    "nfs_client_mount_errors_total",
+    "Count of NFS operations that failed",
+    MOUNT_LABELS,
+);
+
+/// Every metric family that the collector may publish
+const SPECS: &[&Spec] = &[
+    &UP,
+    &COLLECT_ERRORS,
+    &KERNEL_STRUCT_VERSION,
+    &BYTES,
+    &DURATION,
+    &RPCS,
+    &IN_FLIGHT,
+    &OP_BYTES,
+    &OP_COMPLETED,
+    &OP_DURATION,
+    &OP_INFO,
+    &STARTCNT,
+    &DONECNT,
+    &BUSY,
+    &SINCE_PROGRESS,
+    &BUSYTIME,
+    &BUSY_SECONDS,
+    &COUNTER_RESETS,
+    &LAST_RESET,
+    &CACHE_INPROG,
+    &CACHE_NONIDEMPOTENT,
+    &CACHE_HITS,
+    &CACHE_MISSES,
+    &CACHE_NOCACHE,
+    &CACHE_SIZE,
+    &CACHE_TCPPEAK,
+    &THREADS,
+    &NET_PACKETS,
+    &NET_CONNECTIONS,
+    &RPC_CALLS,
+    &RPC_ERRORS,
+    &CLIENTS,
+    &DELEGS,
+    &LAYOUTS,
+    &LOCK_OWNER,
+    &LOCKS,
+    &PNFS_MIRROR_LEVEL,
+    &OPEN_OWNER,
+    &OPENS,
+    &CALLBACK_REQUESTS,
+    &CLIENT_CACHE,
+    &CLIENT_CACHE_HIT_RATIO,
+    &CLIENT_DELEGS,
+    &CLIENT_LAYOUTS,
+    &CLIENT_LOCK_OWNER,
+    &CLIENT_LOCKS,
+    &CLIENT_OPEN_OWNER,
+    &CLIENT_OPENS,
+    &CLIENT_PHYSIOS,
+    &CLIENT_REQUESTS,
+    &CLIENT_RPCS,
+    &CLIENT_RPC_INVALID,
+    &CLIENT_RPC_RETRIES,
+    &CLIENT_RPC_RETRY_RATIO,
+    &CLIENT_RPC_TIMEOUTS,
+    &CLIENT_RPC_TIMEOUT_RATIO,
+    &CLIENT_RPC_UNEXPECTED,
+    &MOUNT_INFO,
+    &MOUNT_BYTES,
+    &MOUNT_REQUESTS,
+    &MOUNT_TRANSMISSIONS,
+    &MOUNT_MAJOR_TIMEOUTS,
+    &MOUNT_SENT_BYTES,
+    &MOUNT_RECEIVED_BYTES,
+    &MOUNT_QUEUE,
+    &MOUNT_RTT,
+    &MOUNT_EXECUTE,
+    &MOUNT_ERRORS,
+];
+
+// node_exporter's nfsd metrics, for sharing dashboards with Linux servers
+const NODE_REQUESTS: Spec = Spec::counter(
+    "node_nfsd_requests_total",
+    "Number of NFSd requests by method and protocol",
+    &["proto", "method"],
+);
+const NODE_SERVER_RPCS: Spec = Spec::counter(
+    "node_nfsd_server_rpcs_total",
+    "Total number of NFSd RPCs",
+    &[],
+);
+const NODE_RPC_ERRORS: Spec = Spec::counter(
+    "node_nfsd_rpc_errors_total",
+    "Total number of NFSd RPC errors by error type",
+    &["error"],
+);
+const NODE_PACKETS: Spec = Spec::counter(
+    "node_nfsd_packets_total",
+    "Total NFSd network packets by protocol type",
+    &["proto"],
+);
+const NODE_CONNECTIONS: Spec = Spec::counter(
+    "node_nfsd_connections_total",
+    "Total number of NFSd TCP connections",
+    &[],
+);
+const NODE_THREADS: Spec = Spec::gauge(
+    "node_nfsd_server_threads",
+    "Total number of NFSd kernel threads that are running",
+    &[],
+);
+const NODE_CACHE_HITS: Spec = Spec::counter(
+    "node_nfsd_reply_cache_hits_total",
+    "Total NFSd reply cache hits",
+    &[],
+);
+const NODE_CACHE_MISSES: Spec = Spec::counter(
+    "node_nfsd_reply_cache_misses_total",
+    "Total NFSd reply cache misses",
+    &[],
+);
+const NODE_CACHE_NOCACHE: Spec = Spec::counter(
+    "node_nfsd_reply_cache_nocache_total",
+    "Total NFSd requests that bypassed the reply cache",
+    &[],
+);
+const NODE_BYTES_READ: Spec = Spec::counter(
+    "node_nfsd_disk_bytes_read_total",
+    "Total NFSd bytes read",
+    &[],
+);
+const NODE_BYTES_WRITTEN: Spec = Spec::counter(
+    "node_nfsd_disk_bytes_written_total",
+    "Total NFSd bytes written",
+    &[],
+);
+
+/// Every metric family published under node_exporter's names
+const NODE_SPECS: &[&Spec] = &[
+    &NODE_REQUESTS,
+    &NODE_SERVER_RPCS,
+    &NODE_RPC_ERRORS,
+    &NODE_PACKETS,
+    &NODE_CONNECTIONS,
+    &NODE_THREADS,
+    &NODE_CACHE_HITS,
+    &NODE_CACHE_MISSES,
+    &NODE_CACHE_NOCACHE,
+    &NODE_BYTES_READ,
+    &NODE_BYTES_WRITTEN,
+];
+
+/// The `proto` label that node_exporter would give an operation.  The kernel
+/// doesn't count requests per protocol version, so every operation that NFS v2
+/// or v3 has gets `proto="3"`, even when it was sent over v4.  Only v4's own
+/// operations get `proto="4"`.
+fn node_proto(op: &nfs::Op) -> &'static str {
+    match op.version {
+        nfs::Version::V2 | nfs::Version::V3 => "3",
+        _ => "4",
+    }
+}
+
+/// The `method` label that node_exporter would give an operation.  It takes
+/// its names from Linux's procfs, which spells a few differently.
+fn node_method(op: &nfs::Op) -> &'static str {
+    match op.name {
+        "CreateV4" => "Create",
+        "LockT" => "Lockt",
+        "LockU" => "Locku",
+        "LookupP" => "LookupRoot",
+        "SetClientId" => "SetClientID",
+        "SetClientIdConfirm" => "SetClientIDConfirm",
+        name => name,
+    }
+}
+
+/// The metric families of a single scrape, as they're being built.  Each is
+/// keyed by its published name, and remembers its spec.
+#[derive(Debug)]
+struct Families {
+    schema:   Schema,
+    families: BTreeMap<&'static str, (&'static Spec, MetricFamily)>,
+    /// When the counters now being published started counting
+    since:    SystemTime,
+    created:  BTreeMap<&'static str, SystemTime>,
+}
+
+impl Families {
+    fn new(schema: Schema, since: SystemTime) -> Self {
+        Families {
+            schema,
+            families: BTreeMap::new(),
+            since,
+            created: BTreeMap::new(),
+        }
+    }
+
+    /// Add one series, under each of the metric's names.  `values` are the
+    /// values of the spec's labels, in order.
+    fn set(&mut self, spec: &'static Spec, values: &[&str], value: f64) {
+        debug_assert_eq!(spec.labels.len(), values.len());
+        let mut labels = spec
+            .labels
+            .iter()
+            .zip(values)
+            .map(|(name, value)| {
+                let mut lp = LabelPair::default();
+                lp.set_name(name.to_string());
+                lp.set_value(value.to_string());
+                lp
+            })
+            .collect::<Vec<_>>();
+        labels.sort_by(|a, b| a.name().cmp(b.name()));
+        let mut m = Metric::from_label(labels);
+        if spec.kind == MetricType::COUNTER {
+            let mut counter = Counter::default();
+            counter.set_value(value);
+            m.set_counter(counter);
+        } else {
+            let mut gauge = Gauge::default();
+            gauge.set_value(value);
+            m.set_gauge(gauge);
+        }
+        for (name, help) in spec.names(self.schema) {
+            if spec.kind == MetricType::COUNTER {
+                self.created.entry(name).or_insert(self.since);
+            }
+            let (_, mf) = self.families.entry(name).or_insert_with(|| {
+                let mut mf = MetricFamily::default();
+                mf.set_name(name.to_string());
+                mf.set_help(help);
+                mf.set_field_type(spec.kind);
+                (spec, mf)
+            });
+            mf.mut_metric().push(m.clone());
+        }
+    }
+}
+
+/// Label every series in the family as adjusted for nfsd restarts
+fn label_reset_adjusted(mf: &mut MetricFamily) {
+    for m in mf.mut_metric() {
+        let mut labels = m.take_label();
+        let mut lp = LabelPair::default();
+        lp.set_name("reset_adjusted".to_string());
+        lp.set_value("true".to_string());
+        labels.push(lp);
+        labels.sort_by(|a, b| a.name().cmp(b.name()));
+        m.set_label(labels);
+    }
+}
+
+/// Collects NFS statistics whenever the registry is gathered
+pub struct NfsCollector {
+    options:        Options,
+    source:         SharedSource,
+    descs:          Vec<Desc>,
+    /// Count of collection failures, by errno
+    collect_errors: Mutex<BTreeMap<String, u64>>,
+    /// Client stats from the previous scrape, used to compute ratios
+    prev_client:    Mutex<Option<ClientStat>>,
+    /// Wrap-detection state for counters derived from kernel bintimes
+    wraps:          Mutex<WrapState>,
+    /// Detection of nfsd restarts
+    resets:         Mutex<ResetState>,
+    /// When the exporter started
+    started:        SystemTime,
+    /// When the kernel's counters started, at boot
+    boot:           SystemTime,
+    created:        Created,
+}
+
+impl NfsCollector {
+    /// Publish statistics from `source`, as chosen by `options`
+    pub fn new(source: SharedSource, options: Options) -> Self {
+        let started = SystemTime::now();
+        let boot = nfs::boot_time().unwrap_or(started);
+        let node_specs = if options.node_compat { NODE_SPECS } else { &[] };
+        let descs = SPECS
+            .iter()
+            .chain(node_specs)
+            .flat_map(|spec| {
+                spec.names(options.schema).into_iter().map(|(name, help)| {
+                    let labels = spec.labels.iter().map(|l| l.to_string());
+                    Desc::new(
+                        name.to_string(),
+                        help,
+                        labels.collect(),
+                        HashMap::new(),
+                    )
+                    .expect("invalid metric description")
+                })
+            })
+            .collect();
+        NfsCollector {
+            options,
+            source,
+            descs,
+            collect_errors: Mutex::default(),
+            prev_client: Mutex::new(None),
+            wraps: Mutex::default(),
+            resets: Mutex::new(ResetState::new(options.monotonic)),
+            started,
+            boot,
+            created: Created::default(),
+        }
+    }
+
+    /// The start times of the counters published by the latest scrape
+    pub fn created(&self) -> Created {
+        self.created.clone()
+    }
+
+    fn publish_kernel_struct(&self, stat: &NfsStat, out: &mut Families) {
+        if let Some(ks) = &stat.kernel_struct {
+            let osreldate =
+                ks.osreldate.map(|v| v.to_string()).unwrap_or_default();
+            out.set(
+                &KERNEL_STRUCT_VERSION,
+                &[
+                    ks.layout.as_str(),
+                    &ks.layout.vers().to_string(),
+                    &osreldate,
+                ],
+                1.0,
+            );
+        }
+    }
+
+    fn publish_client(&self, stat: &NfsStat, out: &mut Families) {
+        out.since = self.boot;
+        if let Some(callback_rpcs) = &stat.callback_rpcs {
+            for (op, count) in callback_rpcs.iter() {
+                out.set(&CALLBACK_REQUESTS, &[op.name], count as f64);
+            }
+        }
+        if let Some(client) = &stat.client {
+            for (label, cache) in client.cache.by_name() {
+                out.set(&CLIENT_CACHE, &[label, "hit"], cache.hits as f64);
+                out.set(&CLIENT_CACHE, &[label, "miss"], cache.misses as f64);
+            }
+            out.set(&CLIENT_PHYSIOS, &["Read"], client.physios.read as f64);
+            out.set(&CLIENT_PHYSIOS, &["Write"], client.physios.write as f64);
+
+            for (idx, label) in nfs::CLIENT_RPC_NAMES {
+                let count = client.rpcs.0[idx as usize];
+                out.set(&CLIENT_REQUESTS, &[label], count as f64);
+            }
+
+            out.set(&CLIENT_DELEGS, &[], client.state.delegs as f64);
+            out.set(&CLIENT_LAYOUTS, &[], client.state.layouts as f64);
+            for (label, cs) in [
+                ("remote", &client.state.remote),
+                ("local", &client.state.local),
+            ] {
+                out.set(&CLIENT_LOCK_OWNER, &[label], cs.lock_owner as f64);
+                out.set(&CLIENT_LOCKS, &[label], cs.locks as f64);
+                out.set(&CLIENT_OPEN_OWNER, &[label], cs.open_owner as f64);
+                out.set(&CLIENT_OPENS, &[label], cs.opens as f64);
+            }
+
+            out.set(&CLIENT_RPCS, &[], client.requests as f64);
+            out.set(&CLIENT_RPC_RETRIES, &[], client.retries as f64);
+            out.set(&CLIENT_RPC_TIMEOUTS, &[], client.timeouts as f64);
+            out.set(&CLIENT_RPC_UNEXPECTED, &[], client.unexpected as f64);
+            out.set(&CLIENT_RPC_INVALID, &[], client.invalid as f64);
+
+            let mut prev_client = self.prev_client.lock().unwrap();
+            if let Some(prev) = prev_client.as_ref() {
+                out.set(&CLIENT_RPC_RETRY_RATIO, &[], client.retry_ratio(prev));
+                out.set(
+                    &CLIENT_RPC_TIMEOUT_RATIO,
+                    &[],
+                    client.timeout_ratio(prev),
+                );
+                for ((label, cache), (_, prev_cache)) in
+                    client.cache.by_name().into_iter().zip(prev.cache.by_name())
+                {
+                    out.set(
+                        &CLIENT_CACHE_HIT_RATIO,
+                        &[label],
+                        cache.hit_ratio(prev_cache),
+                    );
+                }
+            }
+            *prev_client = Some(*client);
+        }
+        publish_mounts(&stat.mounts, out);
+    }
+
+    fn publish_server(&self, stat: &NfsStat, out: &mut Families) {
+        let mut wraps = self.wraps.lock().unwrap();
+        let mut resets = self.resets.lock().unwrap();
+        if resets.observe(stat, SystemTime::now()) {
+            log::warn!("nfsd's counters were reset");
+        }
+        out.since = self.started;
+        out.set(&COUNTER_RESETS, &[], resets.count() as f64);
+        if let Some(last) = resets.last() {
+            let since_epoch = last
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            out.set(&LAST_RESET, &[], since_epoch.as_secs_f64());
+        }
+        out.since = resets.last_restart().unwrap_or(self.boot);
+        // Publish a counter that nfsd restarts may reset
+        let mut counter =
+            |spec: &'static Spec, label: &'static str, raw: f64| {
+                let values: &[&'static str] = if spec.labels.is_empty() {
+                    &[]
+                } else {
+                    &[label]
+                };
+                out.set(spec, values, resets.adjust(spec.name, values, raw));
+            };
+
+        let bytes = [("Read", stat.bytes.read), ("Write", stat.bytes.write)];
+        for (label, bytes) in bytes {
+            counter(&BYTES, label, bytes as f64);
+        }
+        if let Some(startcnt) = stat.startcnt {
+            counter(&STARTCNT, "", startcnt as f64);
+        }
+        if let Some(donecnt) = stat.donecnt {
+            counter(&DONECNT, "", donecnt as f64);
+        }
+        if let Some(busytime) = &stat.busytime {
+            let busy_seconds = wraps.seconds("busytime", "", busytime);
+            counter(&BUSYTIME, "", busy_seconds * 1e9);
+            counter(&BUSY_SECONDS, "", busy_seconds);
+        }
+
+        let cache = &stat.server_cache;
+        if let Some(hits) = cache.hits {
+            counter(&CACHE_HITS, "", hits as f64);
+        }
+        if let Some(inprog) = cache.inprog {
+            counter(&CACHE_INPROG, "", inprog as f64);
+        }
+        if let Some(nonidem) = cache.nonidem {
+            counter(&CACHE_NONIDEMPOTENT, "", nonidem as f64);
+        }
+        counter(&CACHE_MISSES, "", cache.misses as f64);
+        if let Some(nocache) = cache.nocache {
+            counter(&CACHE_NOCACHE, "", nocache as f64);
+        }
+
+        if let Some(net) = &stat.net {
+            for (label, packets) in [("tcp", net.tcp), ("udp", net.udp)] {
+                counter(&NET_PACKETS, label, packets as f64);
+            }
+            counter(&NET_CONNECTIONS, "", net.tcp_connections as f64);
+        }
+        if let Some(rpc) = &stat.rpc {
+            counter(&RPC_CALLS, "", rpc.calls as f64);
+            let errors = [
+                ("auth", rpc.bad_auth),
+                ("client", rpc.bad_client),
+                ("format", rpc.bad_format),
+            ];
+            for (label, count) in errors {
+                counter(&RPC_ERRORS, label, count as f64);
+            }
+        }
+
+        for (op, count) in stat.server_rpcs.iter() {
+            counter(&RPCS, op.name, count as f64);
+        }
+        for (op, bytes) in stat.op_bytes.iter().flat_map(nfs::PerRPC::iter) {
+            counter(&OP_BYTES, op.name, bytes as f64);
+        }
+        for (op, done) in stat.op_done.iter().flat_map(nfs::PerRPC::iter) {
+            counter(&OP_COMPLETED, op.name, done as f64);
+        }
+        for (op, bt) in stat.op_duration.iter().flat_map(nfs::PerRPC::iter) {
+            let seconds = wraps.seconds("op_duration", op.name, &bt);
+            counter(&OP_DURATION, op.name, seconds);
+            if matches!(op.name, "Read" | "Write" | "Commit") {
+                counter(&DURATION, op.name, seconds * 1e9);
+            }
+        }
+
+        if let Some(in_flight) = stat.in_flight() {
+            out.set(&IN_FLIGHT, &[], in_flight as f64);
+            out.set(&BUSY, &[], if in_flight > 0 { 1.0 } else { 0.0 });
+        }
+        if let Some(since_progress) = stat.since_progress {
+            out.set(&SINCE_PROGRESS, &[], since_progress as f64 / 1e9);
+        }
+        if let Some(threads) = stat.threads {
+            out.set(&THREADS, &[], threads as f64);
+        }
+        if let Some(size) = cache.size {
+            out.set(&CACHE_SIZE, &[], size as f64);
+        }
+        if let Some(tcp_peak) = cache.tcp_peak {
+            out.set(&CACHE_TCPPEAK, &[], tcp_peak as f64);
+        }
+
+        if let Some(misc) = &stat.server_misc {
+            out.set(&CLIENTS, &[], misc.clients as f64);
+            out.set(&DELEGS, &[], misc.delegs as f64);
+            out.set(&LAYOUTS, &[], misc.layouts as f64);
+            out.set(&LOCK_OWNER, &[], misc.lock_owner as f64);
+            out.set(&LOCKS, &[], misc.locks as f64);
+            out.set(&OPEN_OWNER, &[], misc.open_owner as f64);
+            out.set(&OPENS, &[], misc.opens as f64);
+        }
+        if let Some(mirror_level) = stat.pnfs.mirror_level {
+            out.set(&PNFS_MIRROR_LEVEL, &[], mirror_level as f64);
+        }
+
+        for op in nfs::SERVER_OPS {
+            let labels = [op.name, op.version.as_str(), op.category.as_str()];
+            out.set(&OP_INFO, &labels, 1.0);
+        }
+
+        if self.options.node_compat {
+            publish_node(stat, &mut resets, out);
+        }
+    }
+}
+
+/// Publish server statistics under node_exporter's names and labels.  Only
+/// those that node_exporter has are published.
+fn publish_node(stat: &NfsStat, resets: &mut ResetState, out: &mut Families) {
+    let mut counter =
+        |spec: &'static Spec, labels: &[&'static str], raw: f64| {
+            out.set(spec, labels, resets.adjust(spec.name, labels, raw));
+        };
+
+    for (op, count) in stat.server_rpcs.iter() {
+        let labels = [node_proto(op), node_method(op)];
+        counter(&NODE_REQUESTS, &labels, count as f64);
+    }
+    if let Some(rpc) = &stat.rpc {
+        counter(&NODE_SERVER_RPCS, &[], rpc.calls as f64);
+        let errors = [
+            ("auth", rpc.bad_auth),
+            ("cInt", rpc.bad_client),
+            ("fmt", rpc.bad_format),
+        ];
+        for (label, count) in errors {
+            counter(&NODE_RPC_ERRORS, &[label], count as f64);
+        }
+    }
+    if let Some(net) = &stat.net {
+        for (label, packets) in [("tcp", net.tcp), ("udp", net.udp)] {
+            counter(&NODE_PACKETS, &[label], packets as f64);
+        }
+        counter(&NODE_CONNECTIONS, &[], net.tcp_connections as f64);
+    }
+    let cache = &stat.server_cache;
+    if let Some(hits) = cache.hits {
+        counter(&NODE_CACHE_HITS, &[], hits as f64);
+    }
+    counter(&NODE_CACHE_MISSES, &[], cache.misses as f64);
+    if let Some(nocache) = cache.nocache {
+        counter(&NODE_CACHE_NOCACHE, &[], nocache as f64);
+    }
+    counter(&NODE_BYTES_READ, &[], stat.bytes.read as f64);
+    counter(&NODE_BYTES_WRITTEN, &[], stat.bytes.write as f64);
+    if let Some(threads) = stat.threads {
+        out.set(&NODE_THREADS, &[], threads as f64);
+    }
+}
+
+/// Publish the statistics of every NFS mount
+fn publish_mounts(mounts: &[nfs::MountStat], out: &mut Families) {
+    type Field = fn(&nfs::MountOp) -> Option<f64>;
+    let per_op: [(&'static Spec, Field); 9] = [
+        (&MOUNT_REQUESTS, |op| Some(op.ops as f64)),
+        (&MOUNT_TRANSMISSIONS, |op| Some(op.transmissions as f64)),
+        (&MOUNT_MAJOR_TIMEOUTS, |op| Some(op.major_timeouts as f64)),
+        (&MOUNT_SENT_BYTES, |op| Some(op.bytes_sent as f64)),
+        (&MOUNT_RECEIVED_BYTES, |op| Some(op.bytes_received as f64)),
+        (&MOUNT_QUEUE, |op| Some(op.queue_ms as f64 / 1e3)),
+        (&MOUNT_RTT, |op| Some(op.rtt_ms as f64 / 1e3)),
+        (&MOUNT_EXECUTE, |op| Some(op.execute_ms as f64 / 1e3)),
+        (&MOUNT_ERRORS, |op| op.errors.map(|e| e as f64)),
+    ];
+    for mount in mounts {
+        let (mp, server, export) = (
+            mount.mountpoint.as_str(),
+            mount.server.as_str(),
+            mount.export.as_str(),
+        );
+        out.set(&MOUNT_INFO, &[mp, server, export, &mount.version], 1.0);
+        let b = &mount.bytes;
+        let bytes = [
+            ("Read", "normal", b.normal.read),
+            ("Write", "normal", b.normal.write),
+            ("Read", "direct", b.direct.read),
+            ("Write", "direct", b.direct.write),
+            ("Read", "server", b.server.read),
+            ("Write", "server", b.server.write),
+        ];
+        for (method, kind, bytes) in bytes {
+            let labels = [mp, server, export, method, kind];
+            out.set(&MOUNT_BYTES, &labels, bytes as f64);
+        }
+        for (name, op) in mount.ops.iter() {
+            for (spec, field) in per_op.iter() {
+                if let Some(v) = field(op) {
+                    out.set(spec, &[mp, server, export, name], v);
+                }
+            }
+        }
+    }
+}
+
+impl Collector for NfsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut out = Families::new(self.options.schema, self.started);
+        let r = self.source.lock().unwrap().nfsstat();
+        let mut collect_errors = self.collect_errors.lock().unwrap();
+        match r {
+            Ok(stat) => {
+                out.set(&UP, &[], 1.0);
+                self.publish_kernel_struct(&stat, &mut out);
+                if self.options.client {
+                    self.publish_client(&stat, &mut out);
+                }
+                if self.options.server && !stat.nfsd_absent {
+                    self.publish_server(&stat, &mut out);
+                }
+            }
+            Err(e) => {
+                log::error!("Cannot collect NFS stats: {e}");
+                out.set(&UP, &[], 0.0);
+                *collect_errors.entry(e.errno()).or_default() += 1;
+            }
+        }
+        out.since = self.started;
+        for (errno, count) in collect_errors.iter() {
+            out.set(&COLLECT_ERRORS, &[errno], *count as f64);
+        }
+
+        *self.created.lock().unwrap() = out.created;
+        let resets = self.resets.lock().unwrap();
+        out.families
+            .into_values()
+            .map(|(spec, mut mf)| {
+                if resets.is_adjusted(spec.name) {
+                    label_reset_adjusted(&mut mf);
+                }
+                mf
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Registry, TextEncoder};
+
+    use super::*;
+    use crate::source::Error;
+
+    /// A source that returns canned results
+    struct Canned(Vec<Result<NfsStat, i32>>);
+
+    impl StatsSource for Canned {
+        fn nfsstat(&mut self) -> Result<NfsStat, Error> {
+            self.0.remove(0).map_err(|errno| {
+                std::io::Error::from_raw_os_error(errno).into()
+            })
+        }
+    }
+
+    fn scrape(registry: &Registry) -> String {
+        TextEncoder::new()
+            .encode_to_string(&registry.gather())
+            .unwrap()
+    }
+
+    /// Server statistics only, with the default names
+    const SERVER: Options = Options {
+        client:      false,
+        server:      true,
+        schema:      Schema::V1,
+        node_compat: false,
+        monotonic:   false,
+    };
+
+    /// A collector that serves `results`, in order
+    fn collector(
+        options: Options,
+        results: Vec<Result<NfsStat, i32>>,
+    ) -> NfsCollector {
+        let source: SharedSource =
+            Arc::new(Mutex::new(Box::new(Canned(results))));
+        NfsCollector::new(source, options)
+    }
+
+    fn registry(collector: NfsCollector) -> Registry {
+        let registry = Registry::new();
+        registry.register(Box::new(collector)).unwrap();
+        registry
+    }
+
+    fn stat() -> NfsStat {
+        let mut stat = NfsStat {
+            donecnt: Some(1000),
+            startcnt: Some(1002),
+            ..Default::default()
+        };
+        // Access
+        stat.server_rpcs.0[0] = 42;
+        stat
+    }
+
+    mod collect {
+        use super::*;
+
+        /// Kernel counters are published as counters
+        #[test]
+        fn counter_type() {
+            let registry = registry(collector(SERVER, vec![Ok(stat())]));
+            let body = scrape(&registry);
+            assert!(body.contains("# TYPE nfs_nfsd_requests_total counter\n"));
+            assert!(body.contains("# TYPE nfs_nfsd_done_count counter\n"));
+            assert!(body.contains("\nnfs_nfsd_done_count 1000\n"));
+        }
+
+        /// Levels are still published as gauges
+        #[test]
+        fn gauge_type() {
+            let registry = registry(collector(SERVER, vec![Ok(stat())]));
+            let body = scrape(&registry);
+            assert!(body.contains("# TYPE nfs_nfsd_ops_in_flight gauge\n"));
+            assert!(body.contains("\nnfs_nfsd_ops_in_flight 2\n"));
+            assert!(body.contains("# TYPE nfs_up gauge\n"));
+        }
+
+        /// Only the requested statistics get published
+        #[test]
+        fn client_only() {
+            let registry = registry(collector(
+                Options {
+                    client: true,
+                    server: false,
+                    ..SERVER
+                },
+                vec![Ok(stat())],
+            ));
+            let body = scrape(&registry);
+            assert!(body.contains("\nnfs_up 1\n"));
+            assert!(!body.contains("nfs_nfsd_"));
+        }
+
+        /// Without nfsd, there's nothing to publish for it
+        #[test]
+        fn nfsd_absent() {
+            let stat = NfsStat {
+                nfsd_absent: true,
+                ..Default::default()
+            };
+            let registry = registry(collector(
+                Options {
+                    client: true,
+                    ..SERVER
+                },
+                vec![Ok(stat)],
+            ));
+            let body = scrape(&registry);
+            assert!(body.contains("\nnfs_up 1\n"));
+            assert!(!body.contains("nfs_nfsd_"));
+        }
+
+        /// A failed collection publishes nothing but the failure
+        #[test]
+        fn error() {
+            let results =
+                vec![Ok(stat()), Err(libc::ENOENT), Err(libc::ENOENT)];
+            let registry = registry(collector(SERVER, results));
+            scrape(&registry);
+            scrape(&registry);
+            let body = scrape(&registry);
+            assert!(body.contains("\nnfs_up 0\n"));
+            assert!(body.contains("nfs_collect_errors_total{errno=\"2\"} 2\n"));
+            assert!(!body.contains("nfs_nfsd_requests_total"));
+        }
+
+        /// In monotonic mode, adjusted counters say so
+        #[test]
+        fn reset_adjusted() {
+            let mut after = stat();
+            after.donecnt = Some(10);
+            after.startcnt = Some(10);
+            let results = vec![Ok(stat()), Ok(after)];
+            let registry = registry(collector(
+                Options {
+                    monotonic: true,
+                    ..SERVER
+                },
+                results,
+            ));
+            scrape(&registry);
+            let body = scrape(&registry);
+            assert!(body.contains("\nnfs_nfsd_counter_resets_total 1\n"));
+            assert!(body.contains(
+                "nfs_nfsd_done_count{reset_adjusted=\"true\"} 1010\n"
+            ));
+        }
+    }
+
+    mod schema {
+        use super::*;
+
+        fn body(schema: Schema) -> String {
+            let mut stat = stat();
+            stat.busytime = Some(nfs::Bintime::default());
+            scrape(&registry(collector(
+                Options {
+                    client: true,
+                    schema,
+                    ..SERVER
+                },
+                vec![Ok(stat)],
+            )))
+        }
+
+        #[test]
+        fn v1() {
+            let body = body(Schema::V1);
+            assert!(body.contains("\nnfs_nfsd_done_count 1000\n"));
+            assert!(body.contains("\nnfs_nfsd_busytime 0\n"));
+            assert!(body.contains("\nnfs_nfsd_busy_seconds_total 0\n"));
+            assert!(!body.contains("nfs_nfsd_operations_completed_total"));
+            assert!(!body.contains("deprecated"));
+        }
+
+        #[test]
+        fn v2() {
+            let body = body(Schema::V2);
+            assert!(
+                body.contains("\nnfs_nfsd_operations_completed_total 1000\n")
+            );
+            assert!(body.contains("\nnfs_nfsd_busy_seconds_total 0\n"));
+            assert!(body.contains("\nnfs_nfsd_server_cache_misses_total 0\n"));
+            assert!(!body.contains("nfs_nfsd_done_count"));
+            assert!(!body.contains("nfs_nfsd_busytime"));
+            assert!(!body.contains("\nnfs_nfsd_server_cache_misses "));
+        }
+
+        /// With both schemas, the v1 names are marked deprecated
+        #[test]
+        fn both() {
+            let body = body(Schema::Both);
+            assert!(body.contains("\nnfs_nfsd_done_count 1000\n"));
+            assert!(
+                body.contains("\nnfs_nfsd_operations_completed_total 1000\n")
+            );
+            assert!(body.contains(concat!(
+                "# HELP nfs_nfsd_done_count Total number of operations ",
+                "completed since boot (deprecated; use ",
+                "nfs_nfsd_operations_completed_total instead)\n"
+            )));
+            assert!(body.contains(concat!(
+                "# HELP nfs_nfsd_busytime Total time in ns that nfsd was busy ",
+                "with at least one operation (deprecated; use ",
+                "nfs_nfsd_busy_seconds_total instead)\n"
+            )));
+            assert!(body.contains(concat!(
+                "# HELP nfs_nfsd_operations_completed_total Total number of ",
+                "operations completed since boot\n"
+            )));
+            assert!(body.contains(
+                "# HELP nfs_nfsd_requests_total Count of server RPCs\n"
+            ));
+        }
+
+        /// Renamed counters are still adjusted for resets
+        #[test]
+        fn reset_adjusted() {
+            let mut after = stat();
+            after.donecnt = Some(10);
+            after.startcnt = Some(10);
+            let results = vec![Ok(stat()), Ok(after)];
+            let registry = registry(collector(
+                Options {
+                    schema: Schema::V2,
+                    monotonic: true,
+                    ..SERVER
+                },
+                results,
+            ));
+            scrape(&registry);
+            let body = scrape(&registry);
+            assert!(body.contains(concat!(
+                "nfs_nfsd_operations_completed_total",
+                "{reset_adjusted=\"true\"} 1010\n"
+            )));
+        }
+
+        /// Every name is unique within each schema
+        #[test]
+        fn unique() {
+            for schema in [Schema::V1, Schema::V2, Schema::Both] {
+                for node_compat in [false, true] {
+                    registry(collector(
+                        Options {
+                            client: true,
+                            schema,
+                            node_compat,
+                            ..SERVER
+                        },
+                        vec![],
+                    ));
+                }
+            }
+        }
+    }
+
+    mod node {
+        use super::*;
+
+        fn op(name: &str) -> &'static nfs::Op {
+            nfs::SERVER_OPS.iter().find(|op| op.name == name).unwrap()
+        }
+
+        fn body(node_compat: bool) -> String {
+            let mut stat = stat();
+            // Close
+            stat.server_rpcs.0[1] = 7;
+            stat.rpc = Some(nfs::ServerRpc {
+                calls:      100,
+                bad_auth:   1,
+                bad_client: 2,
+                bad_format: 3,
+            });
+            stat.threads = Some(8);
+            scrape(&registry(collector(
+                Options {
+                    node_compat,
+                    ..SERVER
+                },
+                vec![Ok(stat)],
+            )))
+        }
+
+        #[test]
+        fn proto() {
+            assert_eq!(node_proto(op("Read")), "3");
+            assert_eq!(node_proto(op("ReadDirPlus")), "3");
+            assert_eq!(node_proto(op("Close")), "4");
+            assert_eq!(node_proto(op("Copy")), "4");
+        }
+
+        #[test]
+        fn method() {
+            assert_eq!(node_method(op("Read")), "Read");
+            assert_eq!(node_method(op("CreateV4")), "Create");
+            assert_eq!(node_method(op("LockT")), "Lockt");
+            assert_eq!(node_method(op("LookupP")), "LookupRoot");
+            assert_eq!(node_method(op("SetClientId")), "SetClientID");
+        }
+
+        #[test]
+        fn published() {
+            let body = body(true);
+            assert!(body.contains(concat!(
+                "node_nfsd_requests_total",
+                "{method=\"Access\",proto=\"3\"} 42\n"
+            )));
+            assert!(body.contains(concat!(
+                "node_nfsd_requests_total",
+                "{method=\"Close\",proto=\"4\"} 7\n"
+            )));
+            assert!(body.contains("\nnode_nfsd_server_rpcs_total 100\n"));
+            assert!(
+                body.contains("node_nfsd_rpc_errors_total{error=\"cInt\"} 2\n")
+            );
+            assert!(body.contains("\nnode_nfsd_server_threads 8\n"));
+            assert!(body.contains("# TYPE node_nfsd_requests_total counter\n"));
+            // The native names are still published
+            assert!(
+                body.contains("nfs_nfsd_requests_total{method=\"Close\"} 7\n")
+            );
+        }
+
+        #[test]
+        fn disabled() {
+            assert!(!body(false).contains("node_nfsd_"));
+        }
+
+        /// v3 Create and v4 CreateV4 share a method name, but not their
+        /// adjustments for resets
+        #[test]
+        fn reset_adjusted() {
+            let pos = |name| {
+                nfs::SERVER_OPS
+                    .iter()
+                    .position(|op| op.name == name)
+                    .unwrap()
+            };
+            let sample = |startcnt, create, create_v4| {
+                let mut stat = stat();
+                stat.donecnt = Some(startcnt);
+                stat.startcnt = Some(startcnt);
+                stat.server_rpcs.0[pos("Create")] = create;
+                stat.server_rpcs.0[pos("CreateV4")] = create_v4;
+                stat
+            };
+            let results = vec![
+                Ok(sample(1000, 50, 5)),
+                Ok(sample(10, 2, 1)),
+                Ok(sample(20, 3, 2)),
+            ];
+            let registry = registry(collector(
+                Options {
+                    node_compat: true,
+                    monotonic: true,
+                    ..SERVER
+                },
+                results,
+            ));
+            let series = |body: &str, proto| {
+                let prefix = format!(
+                    "node_nfsd_requests_total{{method=\"Create\",proto=\"\
+                     {proto}\",reset_adjusted=\"true\"}} "
+                );
+                body.lines()
+                    .find_map(|line| line.strip_prefix(&prefix))
+                    .map(|v| v.parse::<f64>().unwrap())
+            };
+            let body = scrape(&registry);
+            assert_eq!(series(&body, "3"), Some(50.0));
+            assert_eq!(series(&body, "4"), Some(5.0));
+            let body = scrape(&registry);
+            assert_eq!(series(&body, "3"), Some(52.0));
+            assert_eq!(series(&body, "4"), Some(6.0));
+            let body = scrape(&registry);
+            assert_eq!(series(&body, "3"), Some(53.0));
+            assert_eq!(series(&body, "4"), Some(7.0));
+        }
+    }
+
+    mod created {
+        use super::*;
+
+        /// nfsd's counters start at boot, or at their latest reset
+        #[test]
+        fn reset() {
+            let mut after = stat();
+            after.donecnt = Some(10);
+            after.startcnt = Some(10);
+            let results = vec![Ok(stat()), Ok(after)];
+            let collector = collector(SERVER, results);
+            let created = collector.created();
+            let boot = collector.boot;
+            let registry = registry(collector);
+
+            let before_reset = SystemTime::now();
+            scrape(&registry);
+            let done = created.lock().unwrap()["nfs_nfsd_done_count"];
+            assert_eq!(done, boot);
+            scrape(&registry);
+            let created = created.lock().unwrap();
+            assert!(created["nfs_nfsd_done_count"] >= before_reset);
+            assert!(created["nfs_nfsd_counter_resets_total"] <= before_reset);
+            // Gauges don't start
+            assert!(!created.contains_key("nfs_nfsd_ops_in_flight"));
+        }
+
+        /// In monotonic mode, nfsd's counters never start over
+        #[test]
+        fn monotonic() {
+            let mut after = stat();
+            after.donecnt = Some(10);
+            after.startcnt = Some(10);
+            let results = vec![Ok(stat()), Ok(after)];
+            let collector = collector(
+                Options {
+                    monotonic: true,
+                    ..SERVER
+                },
+                results,
+            );
+            let created = collector.created();
+            let boot = collector.boot;
+            let registry = registry(collector);
+            scrape(&registry);
+            scrape(&registry);
+            assert_eq!(created.lock().unwrap()["nfs_nfsd_done_count"], boot);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Reconstruct monotonic counters from kernel counters that may wrap or be
+//! reset.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::nfs::{Bintime, NfsStat};
+
+/// The state of a single series that may wrap.
+#[derive(Clone, Copy, Debug, Default)]
+struct Unwrapper {
+    /// The previous raw value
+    prev:   f64,
+    /// The amount to add to the raw value to get the full value
+    offset: f64,
+}
+
+impl Unwrapper {
+    /// Accept a new raw sample of a counter that wraps at `modulus`, and
+    /// return its full value.
+    fn update(&mut self, raw: f64, modulus: f64) -> f64 {
+        if raw < self.prev {
+            if self.prev >= modulus / 2.0 && raw < modulus / 2.0 {
+                // The counter wrapped around.
+                self.offset += modulus;
+            } else {
+                // The counter was reset, for example because the stats were
+                // zeroed.  Pass that along to Prometheus.
+                self.offset = 0.0;
+            }
+        }
+        self.prev = raw;
+        self.offset + raw
+    }
+}
+
+/// Wrap-detection state for every series that the exporter publishes from a
+/// kernel `bintime`.  Series are identified by metric name and label.
+#[derive(Debug, Default)]
+pub struct WrapState {
+    series: HashMap<(&'static str, &'static str), Unwrapper>,
+}
+
+impl WrapState {
+    /// Return the full value of a series in seconds, accounting for any
+    /// wraps of the kernel's counter since the exporter started.
+    pub fn seconds(
+        &mut self,
+        metric: &'static str,
+        label: &'static str,
+        bt: &Bintime,
+    ) -> f64 {
+        self.series
+            .entry((metric, label))
+            .or_default()
+            .update(bt.as_secs_f64(), Bintime::MODULUS)
+    }
+}
+
+/// The state of a single series that may be adjusted for nfsd resets
+#[derive(Clone, Copy, Debug, Default)]
+struct Offset {
+    /// The previous raw value
+    prev:   f64,
+    /// The sum of the series' values from before each reset
+    offset: f64,
+}
+
+/// Detects when nfsd's counters get reset, and optionally keeps the published
+/// counters monotonic anyway.  Series are identified by metric name and label
+/// values.
+#[derive(Debug, Default)]
+pub struct ResetState {
+    monotonic: bool,
+    /// Number of resets detected since the exporter started
+    count:     u64,
+    /// When the most recent reset was detected
+    last:      Option<SystemTime>,
+    prev:      Option<NfsStat>,
+    series:    HashMap<(&'static str, Vec<&'static str>), Offset>,
+}
+
+impl ResetState {
+    /// If `monotonic` is set, [`ResetState::adjust`] will add the pre-reset
+    /// value to each counter.
+    pub fn new(monotonic: bool) -> Self {
+        ResetState {
+            monotonic,
+            ..Default::default()
+        }
+    }
+
+    /// Number of resets detected since the exporter started
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// When the most recent reset was detected, if ever
+    pub fn last(&self) -> Option<SystemTime> {
+        self.last
+    }
+
+    /// Compare a new sample to the previous one, and return true if nfsd's
+    /// counters were reset in between.  Must be called before publishing
+    /// the sample's counters with [`ResetState::adjust`].
+    pub fn observe(&mut self, stat: &NfsStat, now: SystemTime) -> bool {
+        let reset = self
+            .prev
+            .as_ref()
+            .is_some_and(|prev| stat.is_reset_since(prev));
+        if reset {
+            self.count += 1;
+            self.last = Some(now);
+            for series in self.series.values_mut() {
+                series.offset += series.prev;
+            }
+        }
+        self.prev = Some(stat.clone());
+        reset
+    }
+
+    /// Return the value to publish for a counter, given all of its label
+    /// values.  In monotonic mode, that includes the counter's values from
+    /// before any resets.
+    pub fn adjust(
+        &mut self,
+        metric: &'static str,
+        labels: &[&'static str],
+        raw: f64,
+    ) -> f64 {
+        if !self.monotonic {
+            return raw;
+        }
+        let series = self.series.entry((metric, labels.to_vec())).or_default();
+        series.prev = raw;
+        series.offset + raw
+    }
+
+    /// When the published counters last started over from zero, if since the
+    /// exporter started.  In monotonic mode, they never do.
+    pub fn last_restart(&self) -> Option<SystemTime> {
+        if self.monotonic { None } else { self.last }
+    }
+
+    /// Does the named metric get published with adjustments?
+    pub fn is_adjusted(&self, metric: &str) -> bool {
+        self.monotonic && self.series.keys().any(|(m, _)| *m == metric)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The modulus of a 32-bit `time_t`
+    const MOD32: f64 = 4294967296.0;
+
+    fn bt(sec: u64, frac: u64) -> Bintime {
+        Bintime { sec, frac }
+    }
+
+    mod unwrapper {
+        use super::*;
+
+        #[test]
+        fn monotonic() {
+            let mut u = Unwrapper::default();
+            assert_eq!(u.update(bt(1, 0).as_secs_f64(), MOD32), 1.0);
+            assert_eq!(u.update(bt(2, 1 << 63).as_secs_f64(), MOD32), 2.5);
+        }
+
+        #[test]
+        fn wrap() {
+            let mut u = Unwrapper::default();
+            let before = bt((1 << 32) - 1, 1 << 63).as_secs_f64();
+            assert_eq!(u.update(before, MOD32), MOD32 - 0.5);
+            let after = bt(1, 1 << 62).as_secs_f64();
+            assert_eq!(u.update(after, MOD32), MOD32 + 1.25);
+            // Keep counting after the wrap
+            assert_eq!(u.update(bt(2, 0).as_secs_f64(), MOD32), MOD32 + 2.0);
+        }
+
+        #[test]
+        fn wrap_twice() {
+            let mut u = Unwrapper::default();
+            u.update(bt(3 << 30, 0).as_secs_f64(), MOD32);
+            u.update(bt(1, 0).as_secs_f64(), MOD32);
+            u.update(bt(3 << 30, 0).as_secs_f64(), MOD32);
+            let full = u.update(bt(1, 0).as_secs_f64(), MOD32);
+            assert_eq!(full, 2.0 * MOD32 + 1.0);
+        }
+
+        #[test]
+        fn reset() {
+            let mut u = Unwrapper::default();
+            assert_eq!(u.update(bt(1000, 0).as_secs_f64(), MOD32), 1000.0);
+            assert_eq!(u.update(bt(5, 0).as_secs_f64(), MOD32), 5.0);
+        }
+
+        #[test]
+        fn reset_after_wrap() {
+            let mut u = Unwrapper::default();
+            u.update(bt((1 << 32) - 1, 0).as_secs_f64(), MOD32);
+            u.update(bt(1000, 0).as_secs_f64(), MOD32);
+            assert_eq!(u.update(bt(5, 0).as_secs_f64(), MOD32), 5.0);
+        }
+    }
+
+    mod wrap_state {
+        use super::*;
+
+        /// Each series wraps independently
+        #[test]
+        fn per_series() {
+            let mut ws = WrapState::default();
+            ws.seconds("a", "x", &bt(100, 0));
+            ws.seconds("a", "y", &bt(5, 0));
+            assert_eq!(ws.seconds("a", "x", &bt(101, 0)), 101.0);
+            assert_eq!(ws.seconds("a", "y", &bt(6, 0)), 6.0);
+        }
+    }
+
+    mod bintime {
+        use super::*;
+        use crate::ffi;
+
+        #[test]
+        fn as_secs_f64() {
+            assert_eq!(bt(0, 0).as_secs_f64(), 0.0);
+            assert_eq!(bt(7, 3 << 62).as_secs_f64(), 7.75);
+        }
+
+        /// No precision is lost beyond what a nanosecond count would have
+        #[test]
+        fn beyond_u64_nanoseconds() {
+            let secs = 20_000_000_000u64;
+            assert!(secs.checked_mul(1_000_000_000).is_none());
+            assert_eq!(bt(secs, 0).as_secs_f64(), secs as f64);
+        }
+
+        /// A negative time_t is a wrapped value, not a negative duration
+        #[test]
+        fn from_negative_time_t() {
+            let raw = ffi::bintime {
+                sec:  -1,
+                frac: 1 << 63,
+            };
+            let bt = Bintime::from(&raw);
+            assert_eq!(bt.sec as f64, Bintime::MODULUS - 1.0);
+            assert_eq!(bt.frac, 1 << 63);
+        }
+    }
+
+    mod reset_state {
+        use std::time::Duration;
+
+        use super::*;
+
+        fn stat(startcnt: u64) -> NfsStat {
+            NfsStat {
+                startcnt: Some(startcnt),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn no_reset() {
+            let mut rs = ResetState::new(false);
+            assert!(!rs.observe(&stat(10), SystemTime::UNIX_EPOCH));
+            assert!(!rs.observe(&stat(20), SystemTime::UNIX_EPOCH));
+            assert_eq!(rs.count(), 0);
+            assert_eq!(rs.last(), None);
+        }
+
+        #[test]
+        fn reset() {
+            let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+            let mut rs = ResetState::new(false);
+            rs.observe(&stat(10), SystemTime::UNIX_EPOCH);
+            assert!(rs.observe(&stat(2), t));
+            assert_eq!(rs.count(), 1);
+            assert_eq!(rs.last(), Some(t));
+            // Without monotonic mode, values pass through unchanged
+            assert_eq!(rs.adjust("a", &[], 2.0), 2.0);
+            assert!(!rs.is_adjusted("a"));
+        }
+
+        #[test]
+        fn monotonic() {
+            let t = SystemTime::UNIX_EPOCH;
+            let mut rs = ResetState::new(true);
+            rs.observe(&stat(10), t);
+            assert_eq!(rs.adjust("a", &["x"], 10.0), 10.0);
+            assert_eq!(rs.adjust("a", &["y"], 7.0), 7.0);
+            rs.observe(&stat(2), t);
+            assert_eq!(rs.adjust("a", &["x"], 2.0), 12.0);
+            assert_eq!(rs.adjust("a", &["y"], 1.0), 8.0);
+            rs.observe(&stat(5), t);
+            assert_eq!(rs.adjust("a", &["x"], 5.0), 15.0);
+            rs.observe(&stat(1), t);
+            assert_eq!(rs.adjust("a", &["x"], 1.0), 16.0);
+            assert_eq!(rs.count(), 2);
+            assert!(rs.is_adjusted("a"));
+            assert!(!rs.is_adjusted("b"));
+        }
+
+        /// Series that share their last label value are still distinct
+        #[test]
+        fn multiple_labels() {
+            let t = SystemTime::UNIX_EPOCH;
+            let mut rs = ResetState::new(true);
+            rs.observe(&stat(10), t);
+            assert_eq!(rs.adjust("a", &["3", "x"], 50.0), 50.0);
+            assert_eq!(rs.adjust("a", &["4", "x"], 5.0), 5.0);
+            rs.observe(&stat(2), t);
+            assert_eq!(rs.adjust("a", &["3", "x"], 2.0), 52.0);
+            assert_eq!(rs.adjust("a", &["4", "x"], 1.0), 6.0);
+        }
+    }
+}
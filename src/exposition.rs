@@ -0,0 +1,403 @@
+// vim: tw=80
+//! Formats that metrics can be exposed in, and the choice between them.
+//!
+//! The classic text and delimited protobuf formats come straight from the
+//! prometheus crate.  It has no OpenMetrics encoder, so that one is here.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    time::SystemTime,
+};
+
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+
+/// Content type of OpenMetrics 1.0
+pub const OPENMETRICS_FORMAT: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Units that a metric's name may end with.  OpenMetrics requires that a
+/// metric with a unit be named after it.
+const UNITS: &[&str] = &["bytes", "ratio", "seconds"];
+
+/// An exposition format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Prometheus's classic text format, version 0.0.4
+    Text,
+    OpenMetrics,
+    /// Length-delimited `io.prometheus.client.MetricFamily` messages
+    Protobuf,
+}
+
+impl Format {
+    /// The format's media type, as used in `Accept` headers
+    fn media_type(&self) -> &'static str {
+        match self {
+            Format::Text => "text/plain",
+            Format::OpenMetrics => "application/openmetrics-text",
+            Format::Protobuf => "application/vnd.google.protobuf",
+        }
+    }
+
+    /// Does a media range with these parameters select this format?  The
+    /// protobuf media type covers other messages and encodings too.
+    fn accepts(&self, params: &[&str]) -> bool {
+        match self {
+            Format::Protobuf => {
+                params.contains(&"proto=io.prometheus.client.MetricFamily")
+                    && params.contains(&"encoding=delimited")
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Choose the format that the client prefers, from an `Accept` header.  Ties
+/// go to the richer format.  Anything unrecognized gets the classic text
+/// format.
+pub fn negotiate(accept: &str) -> Format {
+    let mut best = (0.0, Format::Text);
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default();
+        let params = params.collect::<Vec<_>>();
+        let q = params
+            .iter()
+            .filter_map(|p| p.strip_prefix("q="))
+            .filter_map(|q| q.parse::<f64>().ok())
+            .next()
+            .unwrap_or(1.0);
+        for format in [Format::Protobuf, Format::OpenMetrics, Format::Text] {
+            if media_type.eq_ignore_ascii_case(format.media_type())
+                && format.accepts(&params)
+                && q > 0.0
+                && q > best.0
+            {
+                best = (q, format);
+            }
+        }
+    }
+    best.1
+}
+
+/// Escape a label value or help string
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+/// Format a sample value
+fn value(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v == f64::INFINITY {
+        "+Inf".to_string()
+    } else if v == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    v: f64,
+) {
+    out.push_str(name);
+    out.push_str(suffix);
+    if !labels.is_empty() {
+        let labels = labels
+            .iter()
+            .map(|lp| format!("{}=\"{}\"", lp.name(), escape(lp.value())))
+            .collect::<Vec<_>>();
+        write!(out, "{{{}}}", labels.join(",")).unwrap();
+    }
+    writeln!(out, " {}", value(v)).unwrap();
+}
+
+/// Encode metric families as OpenMetrics.  `created` holds the start time of
+/// each counter family, by name.
+///
+/// Counters must be named `*_total` in OpenMetrics.  Legacy counters that
+/// aren't are published with the `unknown` type instead, so their series keep
+/// the same names as in the classic format.  So is a counter whose family
+/// name would clash with such a legacy counter, as happens when publishing
+/// both metric schemas.
+pub fn encode_openmetrics(
+    families: &[MetricFamily],
+    created: &BTreeMap<&'static str, SystemTime>,
+) -> String {
+    let legacy = families
+        .iter()
+        .filter(|mf| mf.get_field_type() == MetricType::COUNTER)
+        .map(MetricFamily::name)
+        .filter(|name| !name.ends_with("_total"))
+        .collect::<HashSet<_>>();
+    let mut out = String::new();
+    for mf in families {
+        let name = mf.name();
+        let (family, kind, suffix) = match mf.get_field_type() {
+            MetricType::COUNTER => match name.strip_suffix("_total") {
+                Some(family) if !legacy.contains(family) => {
+                    (family, "counter", "_total")
+                }
+                _ => (name, "unknown", ""),
+            },
+            MetricType::GAUGE => (name, "gauge", ""),
+            _ => (name, "unknown", ""),
+        };
+        writeln!(out, "# TYPE {family} {kind}").unwrap();
+        if let Some(unit) = UNITS
+            .iter()
+            .find(|unit| family.ends_with(&format!("_{unit}")))
+        {
+            writeln!(out, "# UNIT {family} {unit}").unwrap();
+        }
+        writeln!(out, "# HELP {family} {}", escape(mf.help())).unwrap();
+        let created = created
+            .get(name)
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .filter(|_| kind == "counter");
+        for m in mf.get_metric() {
+            let v = sample_value(m, mf.get_field_type());
+            write_sample(&mut out, family, suffix, m.get_label(), v);
+            if let Some(created) = created {
+                let ts = created.as_secs_f64();
+                write_sample(&mut out, family, "_created", m.get_label(), ts);
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// The value of a sample.  The exporter publishes only counters and gauges.
+fn sample_value(m: &Metric, kind: MetricType) -> f64 {
+    if kind == MetricType::COUNTER {
+        m.get_counter().value()
+    } else {
+        m.get_gauge().value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    mod negotiate {
+        use super::*;
+
+        #[test]
+        fn missing() {
+            assert_eq!(negotiate(""), Format::Text);
+        }
+
+        #[test]
+        fn openmetrics() {
+            let accept = "application/openmetrics-text; version=1.0.0";
+            assert_eq!(negotiate(accept), Format::OpenMetrics);
+        }
+
+        /// What Prometheus sends by default
+        #[test]
+        fn prometheus() {
+            let accept = concat!(
+                "application/openmetrics-text;version=1.0.0,",
+                "application/openmetrics-text;version=0.0.1;q=0.75,",
+                "text/plain;version=0.0.4;q=0.5,*/*;q=0.1"
+            );
+            assert_eq!(negotiate(accept), Format::OpenMetrics);
+        }
+
+        /// What Prometheus sends when configured to scrape protobuf first
+        #[test]
+        fn protobuf() {
+            let accept = concat!(
+                "application/vnd.google.protobuf;",
+                "proto=io.prometheus.client.MetricFamily;encoding=delimited,",
+                "application/openmetrics-text;version=1.0.0;q=0.8,",
+                "text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+            );
+            assert_eq!(negotiate(accept), Format::Protobuf);
+        }
+
+        /// Other protobuf messages or encodings aren't ours to give
+        #[test]
+        fn protobuf_text_encoding() {
+            let accept = concat!(
+                "application/vnd.google.protobuf;",
+                "proto=io.prometheus.client.MetricFamily;encoding=text,",
+                "text/plain;q=0.5"
+            );
+            assert_eq!(negotiate(accept), Format::Text);
+        }
+
+        #[test]
+        fn prefers_text() {
+            let accept = "application/openmetrics-text;q=0.2,text/plain";
+            assert_eq!(negotiate(accept), Format::Text);
+        }
+
+        #[test]
+        fn refused() {
+            let accept = "application/openmetrics-text;q=0,*/*";
+            assert_eq!(negotiate(accept), Format::Text);
+        }
+
+        #[test]
+        fn unknown() {
+            assert_eq!(negotiate("application/json"), Format::Text);
+        }
+    }
+
+    mod encode_openmetrics {
+        use prometheus::proto::{Counter, Gauge};
+
+        use super::*;
+
+        fn family(
+            name: &str,
+            kind: MetricType,
+            labels: &[(&str, &str)],
+            v: f64,
+        ) -> MetricFamily {
+            let labels = labels
+                .iter()
+                .map(|(name, value)| {
+                    let mut lp = LabelPair::default();
+                    lp.set_name(name.to_string());
+                    lp.set_value(value.to_string());
+                    lp
+                })
+                .collect();
+            let mut m = Metric::from_label(labels);
+            if kind == MetricType::COUNTER {
+                let mut counter = Counter::default();
+                counter.set_value(v);
+                m.set_counter(counter);
+            } else {
+                let mut gauge = Gauge::default();
+                gauge.set_value(v);
+                m.set_gauge(gauge);
+            }
+            let mut mf = MetricFamily::default();
+            mf.set_name(name.to_string());
+            mf.set_help("Some \"help\"".to_string());
+            mf.set_field_type(kind);
+            mf.mut_metric().push(m);
+            mf
+        }
+
+        #[test]
+        fn counter() {
+            let mf = family(
+                "nfs_nfsd_requests_total",
+                MetricType::COUNTER,
+                &[("method", "Read")],
+                42.0,
+            );
+            let t = SystemTime::UNIX_EPOCH + Duration::from_millis(1_500);
+            let created = BTreeMap::from([("nfs_nfsd_requests_total", t)]);
+            assert_eq!(
+                encode_openmetrics(&[mf], &created),
+                concat!(
+                    "# TYPE nfs_nfsd_requests counter\n",
+                    "# HELP nfs_nfsd_requests Some \\\"help\\\"\n",
+                    "nfs_nfsd_requests_total{method=\"Read\"} 42\n",
+                    "nfs_nfsd_requests_created{method=\"Read\"} 1.5\n",
+                    "# EOF\n"
+                )
+            );
+        }
+
+        #[test]
+        fn unit() {
+            let mf = family(
+                "nfs_nfsd_busy_seconds_total",
+                MetricType::COUNTER,
+                &[],
+                3.5,
+            );
+            assert_eq!(
+                encode_openmetrics(&[mf], &BTreeMap::new()),
+                concat!(
+                    "# TYPE nfs_nfsd_busy_seconds counter\n",
+                    "# UNIT nfs_nfsd_busy_seconds seconds\n",
+                    "# HELP nfs_nfsd_busy_seconds Some \\\"help\\\"\n",
+                    "nfs_nfsd_busy_seconds_total 3.5\n",
+                    "# EOF\n"
+                )
+            );
+        }
+
+        /// Counters without a _total suffix keep their names
+        #[test]
+        fn legacy_counter() {
+            let t = SystemTime::UNIX_EPOCH;
+            let mf = family("nfs_client_rpcs", MetricType::COUNTER, &[], 7.0);
+            let created = BTreeMap::from([("nfs_client_rpcs", t)]);
+            assert_eq!(
+                encode_openmetrics(&[mf], &created),
+                concat!(
+                    "# TYPE nfs_client_rpcs unknown\n",
+                    "# HELP nfs_client_rpcs Some \\\"help\\\"\n",
+                    "nfs_client_rpcs 7\n",
+                    "# EOF\n"
+                )
+            );
+        }
+
+        /// A legacy counter and its successor can't share a family name
+        #[test]
+        fn clash() {
+            let v1 = family("nfs_client_rpcs", MetricType::COUNTER, &[], 7.0);
+            let v2 =
+                family("nfs_client_rpcs_total", MetricType::COUNTER, &[], 7.0);
+            assert_eq!(
+                encode_openmetrics(&[v1, v2], &BTreeMap::new()),
+                concat!(
+                    "# TYPE nfs_client_rpcs unknown\n",
+                    "# HELP nfs_client_rpcs Some \\\"help\\\"\n",
+                    "nfs_client_rpcs 7\n",
+                    "# TYPE nfs_client_rpcs_total unknown\n",
+                    "# HELP nfs_client_rpcs_total Some \\\"help\\\"\n",
+                    "nfs_client_rpcs_total 7\n",
+                    "# EOF\n"
+                )
+            );
+        }
+
+        #[test]
+        fn gauge() {
+            let mf = family(
+                "nfs_client_cache_hit_ratio",
+                MetricType::GAUGE,
+                &[("cache", "attr\n\"x\"")],
+                f64::NAN,
+            );
+            assert_eq!(
+                encode_openmetrics(&[mf], &BTreeMap::new()),
+                concat!(
+                    "# TYPE nfs_client_cache_hit_ratio gauge\n",
+                    "# UNIT nfs_client_cache_hit_ratio ratio\n",
+                    "# HELP nfs_client_cache_hit_ratio Some \\\"help\\\"\n",
+                    "nfs_client_cache_hit_ratio{cache=\"attr\\n\\\"x\\\"\"} \
+                     NaN\n",
+                    "# EOF\n"
+                )
+            );
+        }
+
+        #[test]
+        fn empty() {
+            assert_eq!(encode_openmetrics(&[], &BTreeMap::new()), "# EOF\n");
+        }
+    }
+}
@@ -48,6 +48,77 @@ pub const NFSV4OPENSHARE_DENY_WRITE: u32 = 2;
 pub const NFSV4OPENSHARE_DENY_BOTH: u32 = 3;
 pub const NFSSVC_GETSTATS: u32 = 4194304;
 pub const NFSSVC_NEWSTRUCT: u32 = 536870912;
+pub const NFSPROC_NULL: u32 = 0;
+pub const NFSPROC_GETATTR: u32 = 1;
+pub const NFSPROC_SETATTR: u32 = 2;
+pub const NFSPROC_LOOKUP: u32 = 3;
+pub const NFSPROC_ACCESS: u32 = 4;
+pub const NFSPROC_READLINK: u32 = 5;
+pub const NFSPROC_READ: u32 = 6;
+pub const NFSPROC_WRITE: u32 = 7;
+pub const NFSPROC_CREATE: u32 = 8;
+pub const NFSPROC_MKDIR: u32 = 9;
+pub const NFSPROC_SYMLINK: u32 = 10;
+pub const NFSPROC_MKNOD: u32 = 11;
+pub const NFSPROC_REMOVE: u32 = 12;
+pub const NFSPROC_RMDIR: u32 = 13;
+pub const NFSPROC_RENAME: u32 = 14;
+pub const NFSPROC_LINK: u32 = 15;
+pub const NFSPROC_READDIR: u32 = 16;
+pub const NFSPROC_READDIRPLUS: u32 = 17;
+pub const NFSPROC_FSSTAT: u32 = 18;
+pub const NFSPROC_FSINFO: u32 = 19;
+pub const NFSPROC_PATHCONF: u32 = 20;
+pub const NFSPROC_COMMIT: u32 = 21;
+pub const NFSPROC_LOOKUPP: u32 = 22;
+pub const NFSPROC_SETCLIENTID: u32 = 23;
+pub const NFSPROC_SETCLIENTIDCFRM: u32 = 24;
+pub const NFSPROC_LOCK: u32 = 25;
+pub const NFSPROC_LOCKU: u32 = 26;
+pub const NFSPROC_OPEN: u32 = 27;
+pub const NFSPROC_CLOSE: u32 = 28;
+pub const NFSPROC_OPENCONFIRM: u32 = 29;
+pub const NFSPROC_LOCKT: u32 = 30;
+pub const NFSPROC_OPENDOWNGRADE: u32 = 31;
+pub const NFSPROC_RENEW: u32 = 32;
+pub const NFSPROC_PUTROOTFH: u32 = 33;
+pub const NFSPROC_RELEASELCKOWN: u32 = 34;
+pub const NFSPROC_DELEGRETURN: u32 = 35;
+pub const NFSPROC_RETDELEGREMOVE: u32 = 36;
+pub const NFSPROC_RETDELEGRENAME1: u32 = 37;
+pub const NFSPROC_RETDELEGRENAME2: u32 = 38;
+pub const NFSPROC_GETACL: u32 = 39;
+pub const NFSPROC_SETACL: u32 = 40;
+pub const NFSPROC_EXCHANGEID: u32 = 41;
+pub const NFSPROC_CREATESESSION: u32 = 42;
+pub const NFSPROC_DESTROYSESSION: u32 = 43;
+pub const NFSPROC_DESTROYCLIENT: u32 = 44;
+pub const NFSPROC_FREESTATEID: u32 = 45;
+pub const NFSPROC_LAYOUTGET: u32 = 46;
+pub const NFSPROC_GETDEVICEINFO: u32 = 47;
+pub const NFSPROC_LAYOUTCOMMIT: u32 = 48;
+pub const NFSPROC_LAYOUTRETURN: u32 = 49;
+pub const NFSPROC_RECLAIMCOMPL: u32 = 50;
+pub const NFSPROC_WRITEDS: u32 = 51;
+pub const NFSPROC_READDS: u32 = 52;
+pub const NFSPROC_COMMITDS: u32 = 53;
+pub const NFSPROC_OPENLAYGET: u32 = 54;
+pub const NFSPROC_CREATELAYGET: u32 = 55;
+pub const NFSPROC_IOADVISE: u32 = 56;
+pub const NFSPROC_ALLOCATE: u32 = 57;
+pub const NFSPROC_COPY: u32 = 58;
+pub const NFSPROC_SEEK: u32 = 59;
+pub const NFSPROC_SEEKDS: u32 = 60;
+pub const NFSPROC_GETEXTATTR: u32 = 61;
+pub const NFSPROC_SETEXTATTR: u32 = 62;
+pub const NFSPROC_RMEXTATTR: u32 = 63;
+pub const NFSPROC_LISTEXTATTR: u32 = 64;
+pub const NFSPROC_BINDCONNTOSESS: u32 = 65;
+pub const NFSPROC_LOOKUPOPEN: u32 = 66;
+pub const NFSPROC_DEALLOCATE: u32 = 67;
+pub const NFSPROC_LAYOUTERROR: u32 = 68;
+pub const NFSPROC_APPENDWRITE: u32 = 69;
+pub const NFSV42_NPROCS: u32 = 70;
 pub const NFSV4OP_OPENCONFIRM: u32 = 20;
 pub const NFSV4OP_OPENDOWNGRADE: u32 = 21;
 pub const NFSV4OP_SETCLIENTIDCFRM: u32 = 36;
@@ -89,6 +160,7 @@ pub const NFSV4OP_GETXATTR: u32 = 72;
 pub const NFSV4OP_SETXATTR: u32 = 73;
 pub const NFSV4OP_LISTXATTRS: u32 = 74;
 pub const NFSV4OP_REMOVEXATTR: u32 = 75;
+pub const NFSV42_NOPS: u32 = 76;
 pub const NFSV4OP_OPILLEGAL: u32 = 10044;
 pub const NFSV4OP_SYMLINK: u32 = 76;
 pub const NFSV4OP_MKDIR: u32 = 77;
@@ -330,3 +402,12 @@ unsafe extern "C" {
         arg2: *mut ::std::os::raw::c_void,
     ) -> ::std::os::raw::c_int;
 }
+unsafe extern "C" {
+    pub fn sysctlbyname(
+        arg1: *const ::std::os::raw::c_char,
+        arg2: *mut ::std::os::raw::c_void,
+        arg3: *mut usize,
+        arg4: *const ::std::os::raw::c_void,
+        arg5: usize,
+    ) -> ::std::os::raw::c_int;
+}
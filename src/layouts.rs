@@ -0,0 +1,477 @@
+//! Support for every revision of the kernel's `struct nfsstatsv1`.
+//!
+//! The generated bindings describe the current layout, `NFSSTATS_V1`, which
+//! FreeBSD has used since 13.0.  Its arrays are padded, so new operations can
+//! be added without changing its size.  FreeBSD 12 and earlier use an older
+//! layout that predates NFS 4.2.  Later kernels still provide it, as
+//! `NFSSTATS_OV1`.
+//!
+//! A single layout covers 13.x, 14.x, and 15.x because the caller, not the
+//! kernel, chooses it.  `nfssvc(NFSSVC_GETSTATS | NFSSVC_NEWSTRUCT)` fills in
+//! whichever layout the `vers` field asks for, converting if need be, and
+//! fails for a `vers` it doesn't know.  So a kernel that rearranged the
+//! structure would have to give it a new `vers`, like 13.0 did.  The bindings
+//! were generated from the 14.4 and 16.0 headers, which agree on every
+//! offset, and the `v1` tests pin those offsets so that regenerating them
+//! can't silently change the layout.
+#[cfg(target_os = "freebsd")]
+use std::os::raw::c_void;
+use std::{
+    io::{Error, ErrorKind, Result},
+    mem,
+    os::raw::c_int,
+};
+
+use bincode_next::{Decode, Encode};
+
+use crate::ffi;
+
+/// The `vers` of the older layout
+pub const NFSSTATS_OV1: c_int = 1;
+/// Number of NFS v4.1 client RPCs
+const NFSV41_NPROCS: usize = 56;
+/// Number of NFS v4.1 server operations, not counting the fake ones
+const NFSV41_NOPS: usize = 59;
+/// Number of NFS v4.1 callback operations
+const NFSV41_CBNOPS: usize = 15;
+/// Number of server operations in the older layout, including the fake ones
+/// for NFS v3 operations that have no NFS v4 equivalent.
+const OV1_NOPS: usize = NFSV41_NOPS + ffi::NFSV4OP_FAKENOPS as usize;
+/// The first `osreldate` to use the current layout
+#[cfg(any(target_os = "freebsd", test))]
+const V1_OSRELDATE: u64 = 1_300_000;
+
+/// The older layout of `struct nfsstatsv1`, as used by FreeBSD 12.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct nfsstatsov1 {
+    pub vers: c_int,
+    pub attrcache_hits: u64,
+    pub attrcache_misses: u64,
+    pub lookupcache_hits: u64,
+    pub lookupcache_misses: u64,
+    pub direofcache_hits: u64,
+    pub direofcache_misses: u64,
+    pub accesscache_hits: u64,
+    pub accesscache_misses: u64,
+    pub biocache_reads: u64,
+    pub read_bios: u64,
+    pub read_physios: u64,
+    pub biocache_writes: u64,
+    pub write_bios: u64,
+    pub write_physios: u64,
+    pub biocache_readlinks: u64,
+    pub readlink_bios: u64,
+    pub biocache_readdirs: u64,
+    pub readdir_bios: u64,
+    pub rpccnt: [u64; NFSV41_NPROCS + 15],
+    pub rpcretries: u64,
+    pub srvrpccnt: [u64; OV1_NOPS],
+    pub srvrpc_errs: u64,
+    pub srv_errs: u64,
+    pub rpcrequests: u64,
+    pub rpctimeouts: u64,
+    pub rpcunexpected: u64,
+    pub rpcinvalid: u64,
+    pub srvcache_inproghits: u64,
+    pub srvcache_idemdonehits: u64,
+    pub srvcache_nonidemdonehits: u64,
+    pub srvcache_misses: u64,
+    pub srvcache_tcppeak: u64,
+    pub srvcache_size: c_int,
+    pub srvclients: u64,
+    pub srvopenowners: u64,
+    pub srvopens: u64,
+    pub srvlockowners: u64,
+    pub srvlocks: u64,
+    pub srvdelegates: u64,
+    pub cbrpccnt: [u64; NFSV41_CBNOPS],
+    pub clopenowners: u64,
+    pub clopens: u64,
+    pub cllockowners: u64,
+    pub cllocks: u64,
+    pub cldelegates: u64,
+    pub cllocalopenowners: u64,
+    pub cllocalopens: u64,
+    pub cllocallockowners: u64,
+    pub cllocallocks: u64,
+    pub srvstartcnt: u64,
+    pub srvdonecnt: u64,
+    pub srvbytes: [u64; OV1_NOPS],
+    pub srvops: [u64; OV1_NOPS],
+    pub srvduration: [ffi::bintime; OV1_NOPS],
+    pub busyfrom: ffi::bintime,
+    pub busytime: ffi::bintime,
+}
+
+impl Default for nfsstatsov1 {
+    fn default() -> Self {
+        // Safe because the struct is plain old data
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+/// Map a server operation's index in the older layout to its index in the
+/// current one.  The fake operations moved to make room for NFS v4.2.
+fn ov1_op_index(i: usize) -> usize {
+    if i < NFSV41_NOPS {
+        i
+    } else {
+        i - NFSV41_NOPS + ffi::NFSV42_NOPS as usize
+    }
+}
+
+impl From<&nfsstatsov1> for ffi::nfsstatsv1 {
+    fn from(o: &nfsstatsov1) -> Self {
+        let mut raw = ffi::nfsstatsv1 {
+            vers: o.vers,
+            attrcache_hits: o.attrcache_hits,
+            attrcache_misses: o.attrcache_misses,
+            lookupcache_hits: o.lookupcache_hits,
+            lookupcache_misses: o.lookupcache_misses,
+            direofcache_hits: o.direofcache_hits,
+            direofcache_misses: o.direofcache_misses,
+            accesscache_hits: o.accesscache_hits,
+            accesscache_misses: o.accesscache_misses,
+            biocache_reads: o.biocache_reads,
+            read_bios: o.read_bios,
+            read_physios: o.read_physios,
+            biocache_writes: o.biocache_writes,
+            write_bios: o.write_bios,
+            write_physios: o.write_physios,
+            biocache_readlinks: o.biocache_readlinks,
+            readlink_bios: o.readlink_bios,
+            biocache_readdirs: o.biocache_readdirs,
+            readdir_bios: o.readdir_bios,
+            rpcretries: o.rpcretries,
+            rpcrequests: o.rpcrequests,
+            rpctimeouts: o.rpctimeouts,
+            rpcunexpected: o.rpcunexpected,
+            rpcinvalid: o.rpcinvalid,
+            srvcache_inproghits: o.srvcache_inproghits,
+            srvcache_nonidemdonehits: o.srvcache_nonidemdonehits,
+            srvcache_misses: o.srvcache_misses,
+            srvcache_tcppeak: o.srvcache_tcppeak,
+            srvcache_size: o.srvcache_size,
+            srvclients: o.srvclients,
+            srvopenowners: o.srvopenowners,
+            srvopens: o.srvopens,
+            srvlockowners: o.srvlockowners,
+            srvlocks: o.srvlocks,
+            srvdelegates: o.srvdelegates,
+            clopenowners: o.clopenowners,
+            clopens: o.clopens,
+            cllockowners: o.cllockowners,
+            cllocks: o.cllocks,
+            cldelegates: o.cldelegates,
+            cllocalopenowners: o.cllocalopenowners,
+            cllocalopens: o.cllocalopens,
+            cllocallockowners: o.cllocallockowners,
+            cllocallocks: o.cllocallocks,
+            srvstartcnt: o.srvstartcnt,
+            srvdonecnt: o.srvdonecnt,
+            busyfrom: o.busyfrom,
+            busytime: o.busytime,
+            ..Default::default()
+        };
+        raw.rpccnt[..NFSV41_NPROCS].copy_from_slice(&o.rpccnt[..NFSV41_NPROCS]);
+        raw.cbrpccnt[..NFSV41_CBNOPS].copy_from_slice(&o.cbrpccnt);
+        for i in 0..OV1_NOPS {
+            let j = ov1_op_index(i);
+            raw.srvrpccnt[j] = o.srvrpccnt[i];
+            raw.srvbytes[j] = o.srvbytes[i];
+            raw.srvops[j] = o.srvops[i];
+            raw.srvduration[j] = o.srvduration[i];
+        }
+        raw
+    }
+}
+
+/// A revision of the kernel's `struct nfsstatsv1`
+#[derive(Clone, Copy, Debug, Decode, Default, Encode, Eq, PartialEq)]
+pub enum Layout {
+    /// `NFSSTATS_OV1`, from FreeBSD 12 and earlier
+    Ov1,
+    /// `NFSSTATS_V1`, from FreeBSD 13.0 and later
+    #[default]
+    V1,
+}
+
+impl Layout {
+    /// Choose the layout used by a kernel.  If its version is unknown, assume
+    /// that it's current.
+    #[cfg(any(target_os = "freebsd", test))]
+    pub fn for_osreldate(osreldate: Option<u64>) -> Self {
+        match osreldate {
+            Some(osreldate) if osreldate < V1_OSRELDATE => Layout::Ov1,
+            _ => Layout::V1,
+        }
+    }
+
+    /// The layout's name, as used by the kernel
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Layout::Ov1 => "NFSSTATS_OV1",
+            Layout::V1 => "NFSSTATS_V1",
+        }
+    }
+
+    /// The size of the layout's struct, in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Layout::Ov1 => mem::size_of::<nfsstatsov1>(),
+            Layout::V1 => mem::size_of::<ffi::nfsstatsv1>(),
+        }
+    }
+
+    /// The value of `vers` that requests this layout
+    pub fn vers(&self) -> c_int {
+        match self {
+            Layout::Ov1 => NFSSTATS_OV1,
+            Layout::V1 => ffi::NFSSTATS_V1 as c_int,
+        }
+    }
+}
+
+/// Which `struct nfsstatsv1` the kernel provided
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct KernelStruct {
+    pub layout:    Layout,
+    /// The kernel's `__FreeBSD_version`, if known
+    pub osreldate: Option<u64>,
+}
+
+/// Check that an image has the `vers` of its layout.
+fn check_vers(layout: Layout, vers: c_int) -> Result<()> {
+    if vers == layout.vers() {
+        Ok(())
+    } else {
+        let msg = format!(
+            "kernel returned nfsstats version {vers}, expected {}",
+            layout.vers()
+        );
+        Err(Error::new(ErrorKind::InvalidData, msg))
+    }
+}
+
+/// Fetch a raw image of the kernel's statistics, in the given layout.
+#[cfg(target_os = "freebsd")]
+pub fn fetch_image(layout: Layout) -> Result<Vec<u8>> {
+    let mut image = vec![0u8; layout.size()];
+    image[..mem::size_of::<c_int>()]
+        .copy_from_slice(&layout.vers().to_ne_bytes());
+    let flag = ffi::NFSSVC_GETSTATS | ffi::NFSSVC_NEWSTRUCT;
+    let r =
+        unsafe { ffi::nfssvc(flag as i32, image.as_mut_ptr() as *mut c_void) };
+    if r != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(image)
+}
+
+/// Decode a raw image of the kernel's statistics, converting it to the
+/// current layout.  The image's layout is identified by its size, and checked
+/// against its `vers`.
+pub fn from_image(image: &[u8]) -> Result<(ffi::nfsstatsv1, Layout)> {
+    let layout = [Layout::V1, Layout::Ov1]
+        .into_iter()
+        .find(|layout| layout.size() == image.len())
+        .ok_or_else(|| {
+            let msg = format!(
+                "nfsstats image is {} bytes, which matches no known layout",
+                image.len()
+            );
+            Error::new(ErrorKind::InvalidData, msg)
+        })?;
+    // Safe because the image is the right size, and any bit pattern is valid
+    // for these structs, which contain only integers.
+    let raw = match layout {
+        Layout::Ov1 => {
+            let ov1: nfsstatsov1 =
+                unsafe { std::ptr::read_unaligned(image.as_ptr().cast()) };
+            check_vers(layout, ov1.vers)?;
+            ffi::nfsstatsv1::from(&ov1)
+        }
+        Layout::V1 => {
+            let raw: ffi::nfsstatsv1 =
+                unsafe { std::ptr::read_unaligned(image.as_ptr().cast()) };
+            check_vers(layout, raw.vers)?;
+            raw
+        }
+    };
+    Ok((raw, layout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The raw bytes of a struct, as the kernel would write them
+    fn image_of<T>(t: &T) -> Vec<u8> {
+        let p = (t as *const T).cast::<u8>();
+        unsafe { std::slice::from_raw_parts(p, mem::size_of::<T>()) }.to_vec()
+    }
+
+    mod from_image {
+        use super::*;
+
+        #[test]
+        fn v1() {
+            let mut raw = ffi::nfsstatsv1 {
+                vers: ffi::NFSSTATS_V1 as c_int,
+                ..Default::default()
+            };
+            raw.srvrpccnt[ffi::NFSV4OP_ALLOCATE as usize] = 9;
+            let (decoded, layout) = from_image(&image_of(&raw)).unwrap();
+            assert_eq!(layout, Layout::V1);
+            assert_eq!(decoded.srvrpccnt[ffi::NFSV4OP_ALLOCATE as usize], 9);
+        }
+
+        #[test]
+        fn ov1() {
+            let mut o = nfsstatsov1 {
+                vers: NFSSTATS_OV1,
+                ..Default::default()
+            };
+            o.srvrpccnt[NFSV41_NOPS] = 3;
+            let (decoded, layout) = from_image(&image_of(&o)).unwrap();
+            assert_eq!(layout, Layout::Ov1);
+            assert_eq!(decoded.srvrpccnt[ffi::NFSV4OP_SYMLINK as usize], 3);
+        }
+
+        /// An image whose vers doesn't match its size
+        #[test]
+        fn wrong_vers() {
+            let raw = ffi::nfsstatsv1 {
+                vers: NFSSTATS_OV1,
+                ..Default::default()
+            };
+            let e = from_image(&image_of(&raw)).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+
+        #[test]
+        fn wrong_size() {
+            let e = from_image(&[0u8; 100]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    /// The offsets of `NFSSTATS_V1`, as generated from the 14.4 and 16.0
+    /// headers.  If regenerated bindings disagree, then the kernel has a new
+    /// layout, which needs its own `Layout`.
+    mod v1 {
+        use super::*;
+
+        #[cfg(target_pointer_width = "64")]
+        #[test]
+        fn offsets() {
+            assert_eq!(mem::size_of::<ffi::nfsstatsv1>(), 5272);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, rpccnt), 152);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvrpccnt), 800);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvlayouts), 1600);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, cbrpccnt), 1744);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvbytes), 2040);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvduration), 3640);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, busytime), 5256);
+        }
+
+        #[cfg(target_pointer_width = "32")]
+        #[test]
+        fn offsets() {
+            assert_eq!(mem::size_of::<ffi::nfsstatsv1>(), 4856);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, rpccnt), 148);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvrpccnt), 796);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvlayouts), 1596);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, cbrpccnt), 1736);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvbytes), 2032);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, srvduration), 3632);
+            assert_eq!(mem::offset_of!(ffi::nfsstatsv1, busytime), 4844);
+        }
+
+        /// The arrays have room for every operation
+        #[test]
+        fn array_lengths() {
+            let raw = ffi::nfsstatsv1::default();
+            let nops = ffi::NFSV42_NOPS + ffi::NFSV4OP_FAKENOPS;
+            assert!(raw.rpccnt.len() >= ffi::NFSV42_NPROCS as usize);
+            assert!(raw.srvrpccnt.len() >= nops as usize);
+            assert!(raw.srvbytes.len() >= nops as usize);
+        }
+    }
+
+    mod layout {
+        use super::*;
+
+        #[test]
+        fn for_osreldate() {
+            assert_eq!(Layout::for_osreldate(Some(1203000)), Layout::Ov1);
+            assert_eq!(Layout::for_osreldate(Some(1300139)), Layout::V1);
+            assert_eq!(Layout::for_osreldate(Some(1403000)), Layout::V1);
+            assert_eq!(Layout::for_osreldate(Some(1500000)), Layout::V1);
+            assert_eq!(Layout::for_osreldate(Some(1600000)), Layout::V1);
+            assert_eq!(Layout::for_osreldate(None), Layout::V1);
+        }
+
+        #[test]
+        fn check_vers_mismatch() {
+            assert!(check_vers(Layout::V1, ffi::NFSSTATS_V1 as c_int).is_ok());
+            let e = check_vers(Layout::V1, NFSSTATS_OV1).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    mod ov1 {
+        use super::*;
+
+        /// NFS v4.1 operations keep their indices
+        #[test]
+        fn v41_op() {
+            let mut o = nfsstatsov1::default();
+            o.srvrpccnt[ffi::NFSV4OP_RECLAIMCOMPL as usize] = 42;
+            o.srvops[ffi::NFSV4OP_READ as usize] = 7;
+            let raw = ffi::nfsstatsv1::from(&o);
+            assert_eq!(raw.srvrpccnt[ffi::NFSV4OP_RECLAIMCOMPL as usize], 42);
+            assert_eq!(raw.srvops[ffi::NFSV4OP_READ as usize], 7);
+        }
+
+        /// The fake NFS v3 operations move past the NFS v4.2 operations
+        #[test]
+        fn fake_op() {
+            let mut o = nfsstatsov1::default();
+            o.srvrpccnt[NFSV41_NOPS] = 1;
+            o.srvbytes[OV1_NOPS - 1] = 2;
+            let raw = ffi::nfsstatsv1::from(&o);
+            assert_eq!(raw.srvrpccnt[ffi::NFSV4OP_SYMLINK as usize], 1);
+            assert_eq!(raw.srvbytes[ffi::NFSV4OP_V3CREATE as usize], 2);
+            assert_eq!(raw.srvrpccnt[ffi::NFSV4OP_ALLOCATE as usize], 0);
+        }
+
+        #[test]
+        fn scalars() {
+            let o = nfsstatsov1 {
+                vers: NFSSTATS_OV1,
+                rpcretries: 3,
+                srvstartcnt: 100,
+                srvdonecnt: 99,
+                cllocallocks: 4,
+                ..Default::default()
+            };
+            let raw = ffi::nfsstatsv1::from(&o);
+            assert_eq!(raw.vers, NFSSTATS_OV1);
+            assert_eq!(raw.rpcretries, 3);
+            assert_eq!(raw.srvstartcnt, 100);
+            assert_eq!(raw.srvdonecnt, 99);
+            assert_eq!(raw.cllocallocks, 4);
+        }
+
+        #[test]
+        fn callbacks() {
+            let mut o = nfsstatsov1::default();
+            o.cbrpccnt[ffi::NFSV4OP_CBSEQUENCE as usize] = 5;
+            let raw = ffi::nfsstatsv1::from(&o);
+            assert_eq!(raw.cbrpccnt[ffi::NFSV4OP_CBSEQUENCE as usize], 5);
+        }
+    }
+}
@@ -0,0 +1,507 @@
+//! Statistics from the Linux kernel's procfs
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::io::{Error, ErrorKind, Result};
+
+use crate::{
+    ffi,
+    nfs::{MountOp, MountStat, NfsStat, PerRPC, PerRW, ServerNet, ServerRpc},
+};
+
+/// Where Linux's nfsd publishes its statistics
+pub const NFSD_PATH: &str = "/proc/net/rpc/nfsd";
+
+/// Where Linux publishes the statistics of every mount
+pub const MOUNTSTATS_PATH: &str = "/proc/self/mountstats";
+
+/// Number of entries in FreeBSD's per-operation arrays, including the fake
+/// operations for NFS v3.
+const NOPS: usize = (ffi::NFSV42_NOPS + ffi::NFSV4OP_FAKENOPS) as usize;
+
+/// The NFS v3 procedures, in the order of the `proc3` line, mapped to the
+/// operation that FreeBSD counts them as.  FreeBSD doesn't count NULL.
+const PROC3_OPS: [Option<u32>; 22] = [
+    None,
+    Some(ffi::NFSV4OP_GETATTR),
+    Some(ffi::NFSV4OP_SETATTR),
+    Some(ffi::NFSV4OP_LOOKUP),
+    Some(ffi::NFSV4OP_ACCESS),
+    Some(ffi::NFSV4OP_READLINK),
+    Some(ffi::NFSV4OP_READ),
+    Some(ffi::NFSV4OP_WRITE),
+    Some(ffi::NFSV4OP_V3CREATE),
+    Some(ffi::NFSV4OP_MKDIR),
+    Some(ffi::NFSV4OP_SYMLINK),
+    Some(ffi::NFSV4OP_MKNOD),
+    Some(ffi::NFSV4OP_REMOVE),
+    Some(ffi::NFSV4OP_RMDIR),
+    Some(ffi::NFSV4OP_RENAME),
+    Some(ffi::NFSV4OP_LINK),
+    Some(ffi::NFSV4OP_READDIR),
+    Some(ffi::NFSV4OP_READDIRPLUS),
+    Some(ffi::NFSV4OP_FSSTAT),
+    Some(ffi::NFSV4OP_FSINFO),
+    Some(ffi::NFSV4OP_PATHCONF),
+    Some(ffi::NFSV4OP_COMMIT),
+];
+
+fn invalid(tag: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("malformed {tag} line"))
+}
+
+/// Parse the first `N` fields of a line as integers.  Any more are ignored.
+fn leading<'a, const N: usize>(
+    tag: &str,
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Result<[u64; N]> {
+    let mut values = [0; N];
+    for v in values.iter_mut() {
+        *v = fields
+            .next()
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| invalid(tag))?;
+    }
+    Ok(values)
+}
+
+/// Parse a line that consists of a count followed by that many integers.
+fn counted<'a>(
+    tag: &str,
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Result<Vec<u64>> {
+    let [n] = leading(tag, &mut fields)?;
+    let values = fields
+        .map(|f| f.parse().map_err(|_| invalid(tag)))
+        .collect::<Result<Vec<u64>>>()?;
+    if values.len() as u64 != n {
+        return Err(invalid(tag));
+    }
+    Ok(values)
+}
+
+/// Parse the contents of `/proc/net/rpc/nfsd`.
+///
+/// Linux counts NFS v3 RPCs and NFS v4 operations separately.  Like FreeBSD,
+/// the result counts them together, by operation.  Statistics that Linux
+/// doesn't report are left as `None`.
+pub fn parse_nfsd(text: &str) -> Result<NfsStat> {
+    let mut nfs_stat = NfsStat::default();
+    let mut ops = [0u64; NOPS];
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(tag) = fields.next() else {
+            continue;
+        };
+        match tag {
+            "rc" => {
+                let [hits, misses, nocache] = leading(tag, fields)?;
+                nfs_stat.server_cache.hits = Some(hits);
+                nfs_stat.server_cache.misses = misses;
+                nfs_stat.server_cache.nocache = Some(nocache);
+            }
+            "io" => {
+                let [read, write] = leading(tag, fields)?;
+                nfs_stat.bytes = PerRW { read, write };
+            }
+            "th" => {
+                let [threads] = leading(tag, fields)?;
+                nfs_stat.threads = Some(threads);
+            }
+            "net" => {
+                let [_, udp, tcp, tcp_connections] = leading(tag, fields)?;
+                nfs_stat.net = Some(ServerNet {
+                    tcp,
+                    tcp_connections,
+                    udp,
+                });
+            }
+            "rpc" => {
+                let [calls, _, bad_format, bad_auth, bad_client] =
+                    leading(tag, fields)?;
+                nfs_stat.rpc = Some(ServerRpc {
+                    bad_auth,
+                    bad_client,
+                    bad_format,
+                    calls,
+                });
+            }
+            "proc3" => {
+                for (count, op) in counted(tag, fields)?.iter().zip(PROC3_OPS) {
+                    if let Some(op) = op {
+                        ops[op as usize] += count;
+                    }
+                }
+            }
+            "proc4ops" => {
+                // Indexed by the protocol's own operation numbers, which
+                // FreeBSD uses too.  Any beyond NFS v4.2 are ignored.
+                let counts = counted(tag, fields)?;
+                let nops = counts.len().min(ffi::NFSV42_NOPS as usize);
+                for (op, count) in ops.iter_mut().zip(&counts[..nops]) {
+                    *op += count;
+                }
+            }
+            _ => (),
+        }
+    }
+    nfs_stat.server_rpcs = PerRPC::from_fn(|i| ops[i]);
+    Ok(nfs_stat)
+}
+
+/// Undo the octal escapes that Linux uses for special characters in paths,
+/// like `\040` for a space.
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = s
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|oct| u8::from_str_radix(oct, 8).ok());
+        if let Some(b) = escaped {
+            out.push(b);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parse a mountstats `device` line.  Returns `None` if it isn't NFS.
+fn parse_device(line: &str) -> Result<Option<MountStat>> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [
+        "device",
+        device,
+        "mounted",
+        "on",
+        mountpoint,
+        "with",
+        "fstype",
+        fstype,
+        ..,
+    ] = fields[..]
+    else {
+        return Err(invalid("device"));
+    };
+    if fstype != "nfs" && fstype != "nfs4" {
+        return Ok(None);
+    }
+    // The export is always an absolute path, and an IPv6 server is bracketed.
+    let (server, export) = device.split_once(":/").ok_or(invalid("device"))?;
+    Ok(Some(MountStat {
+        mountpoint: unescape(mountpoint),
+        server: server.trim_start_matches('[').trim_end_matches(']').into(),
+        export: unescape(&format!("/{export}")),
+        ..Default::default()
+    }))
+}
+
+/// Parse the contents of `/proc/self/mountstats`, returning statistics for
+/// every NFS mount.
+pub fn parse_mountstats(text: &str) -> Result<Vec<MountStat>> {
+    let mut mounts = Vec::new();
+    // The NFS mount currently being parsed, if any
+    let mut mount = None;
+    let mut in_ops = false;
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(tag) = fields.next() else {
+            continue;
+        };
+        if tag == "device" {
+            mounts.extend(mount.take());
+            mount = parse_device(line)?;
+            in_ops = false;
+            continue;
+        }
+        let Some(mount) = mount.as_mut() else {
+            continue;
+        };
+        let tag = tag.trim_end_matches(':');
+        match tag {
+            "opts" => {
+                let opts = fields.next().unwrap_or_default();
+                if let Some(vers) =
+                    opts.split(',').find_map(|o| o.strip_prefix("vers="))
+                {
+                    mount.version = vers.to_string();
+                }
+            }
+            "bytes" => {
+                let [nr, nw, dr, dw, sr, sw] = leading(tag, fields)?;
+                mount.bytes.normal = PerRW {
+                    read:  nr,
+                    write: nw,
+                };
+                mount.bytes.direct = PerRW {
+                    read:  dr,
+                    write: dw,
+                };
+                mount.bytes.server = PerRW {
+                    read:  sr,
+                    write: sw,
+                };
+            }
+            "per-op" => in_ops = true,
+            _ if in_ops => {
+                let [ops, trans, timeouts, sent, received, queue, rtt, exec] =
+                    leading(tag, &mut fields)?;
+                let errors = match fields.next() {
+                    Some(f) => Some(f.parse().map_err(|_| invalid(tag))?),
+                    None => None,
+                };
+                let op = MountOp {
+                    ops,
+                    transmissions: trans,
+                    major_timeouts: timeouts,
+                    bytes_sent: sent,
+                    bytes_received: received,
+                    queue_ms: queue,
+                    rtt_ms: rtt,
+                    execute_ms: exec,
+                    errors,
+                };
+                mount.ops.push((tag.to_string(), op));
+            }
+            _ => (),
+        }
+    }
+    mounts.extend(mount);
+    Ok(mounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod parse_nfsd {
+        use super::*;
+        use crate::nfs::ServerCache;
+
+        /// Counts for proc4ops, where every operation's count is its number
+        fn proc4ops(nops: u32) -> String {
+            let counts = (0..nops).map(|i| i.to_string()).collect::<Vec<_>>();
+            format!("proc4ops {nops} {}\n", counts.join(" "))
+        }
+
+        fn sample(nops: u32) -> String {
+            let mut text = concat!(
+                "rc 3 6 1203\n",
+                "fh 0 0 0 0 0\n",
+                "io 1048576 2097152\n",
+                "th 8 0 0.000 0.000 0.000 0.000 0.000 0.000 0.000 0.000\n",
+                "ra 32 0 0 0 0 0 0 0 0 0 0 0\n",
+                "net 1210 10 1200 4\n",
+                "rpc 1209 3 1 2 0\n",
+                "proc3 22 2 10 1 20 15 0 30 40 3 4 0 0 5 6 7 1 8 9 1 2 1 11\n",
+                "proc4 2 2 300\n",
+            )
+            .to_string();
+            text.push_str(&proc4ops(nops));
+            text
+        }
+
+        fn count(nfs_stat: &NfsStat, name: &str) -> u64 {
+            nfs_stat
+                .server_rpcs
+                .iter()
+                .find(|(op, _)| op.name == name)
+                .unwrap()
+                .1
+        }
+
+        #[test]
+        fn server() {
+            let ns = parse_nfsd(&sample(76)).unwrap();
+            assert_eq!(
+                ns.server_cache,
+                ServerCache {
+                    hits: Some(3),
+                    misses: 6,
+                    nocache: Some(1203),
+                    ..Default::default()
+                }
+            );
+            assert_eq!(ns.bytes.read, 1048576);
+            assert_eq!(ns.bytes.write, 2097152);
+            assert_eq!(ns.threads, Some(8));
+            assert_eq!(
+                ns.net,
+                Some(ServerNet {
+                    tcp:             1200,
+                    tcp_connections: 4,
+                    udp:             10,
+                })
+            );
+            assert_eq!(
+                ns.rpc,
+                Some(ServerRpc {
+                    bad_auth:   2,
+                    bad_client: 0,
+                    bad_format: 1,
+                    calls:      1209,
+                })
+            );
+        }
+
+        /// NFS v3 RPCs and NFS v4 ops are counted together
+        #[test]
+        fn server_rpcs() {
+            let ns = parse_nfsd(&sample(76)).unwrap();
+            // proc3 + proc4ops
+            assert_eq!(count(&ns, "Read"), 30 + ffi::NFSV4OP_READ as u64);
+            assert_eq!(count(&ns, "GetAttr"), 10 + ffi::NFSV4OP_GETATTR as u64);
+            // proc4ops only
+            assert_eq!(count(&ns, "Sequence"), ffi::NFSV4OP_SEQUENCE as u64);
+            assert_eq!(count(&ns, "CreateV4"), ffi::NFSV4OP_CREATE as u64);
+            // proc3 only
+            assert_eq!(count(&ns, "Create"), 3);
+            assert_eq!(count(&ns, "ReadDirPlus"), 9);
+            assert_eq!(count(&ns, "PathConf"), 1);
+        }
+
+        /// Older kernels don't count the newest operations
+        #[test]
+        fn old_proc4ops() {
+            let ns = parse_nfsd(&sample(59)).unwrap();
+            assert_eq!(count(&ns, "Sequence"), ffi::NFSV4OP_SEQUENCE as u64);
+            assert_eq!(count(&ns, "Copy"), 0);
+        }
+
+        /// Statistics that Linux lacks are omitted, not zeroed
+        #[test]
+        fn omitted() {
+            let ns = parse_nfsd(&sample(76)).unwrap();
+            assert_eq!(ns.busytime, None);
+            assert_eq!(ns.client, None);
+            assert_eq!(ns.in_flight(), None);
+            assert_eq!(ns.kernel_struct, None);
+            assert_eq!(ns.op_duration, None);
+            assert_eq!(ns.server_cache.inprog, None);
+            assert_eq!(ns.server_misc, None);
+        }
+
+        #[test]
+        fn malformed() {
+            let e = parse_nfsd("io 1048576\n").unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), "malformed io line");
+            let e = parse_nfsd("proc3 22 1 2 3\n").unwrap_err();
+            assert_eq!(e.to_string(), "malformed proc3 line");
+        }
+    }
+    mod parse_mountstats {
+        use super::*;
+
+        const V3: &str = include_str!("../tests/data/mountstats/v3");
+        const V4_1: &str = include_str!("../tests/data/mountstats/v4.1");
+        const V4_2: &str = include_str!("../tests/data/mountstats/v4.2");
+
+        fn op<'a>(mount: &'a MountStat, name: &str) -> &'a MountOp {
+            &mount.ops.iter().find(|(n, _)| n == name).unwrap().1
+        }
+
+        #[test]
+        fn v3() {
+            let mounts = parse_mountstats(V3).unwrap();
+            assert_eq!(mounts.len(), 2);
+
+            let home = &mounts[0];
+            assert_eq!(home.mountpoint, "/mnt/home dir");
+            assert_eq!(home.server, "fs1.example.com");
+            assert_eq!(home.export, "/export/home");
+            assert_eq!(home.version, "3");
+            assert_eq!(home.bytes.normal.read, 3461021696);
+            assert_eq!(home.bytes.normal.write, 1618998272);
+            assert_eq!(home.bytes.direct, PerRW::default());
+            assert_eq!(home.bytes.server.read, 3461021696);
+            assert_eq!(home.ops.len(), 22);
+            assert_eq!(
+                *op(home, "READ"),
+                MountOp {
+                    ops:            3301,
+                    transmissions:  3301,
+                    major_timeouts: 0,
+                    bytes_sent:     422528,
+                    bytes_received: 3461444128,
+                    queue_ms:       190,
+                    rtt_ms:         21402,
+                    execute_ms:     21688,
+                    errors:         None,
+                }
+            );
+            let write = op(home, "WRITE");
+            assert_eq!(write.transmissions, 1546);
+            assert_eq!(write.major_timeouts, 1);
+
+            let scratch = &mounts[1];
+            assert_eq!(scratch.mountpoint, "/scratch");
+            assert_eq!(scratch.server, "fs2.example.com");
+            assert_eq!(scratch.export, "/scratch");
+            assert_eq!(op(scratch, "FSINFO").ops, 2);
+        }
+
+        /// Older kernels don't count errors
+        #[test]
+        fn v4_1() {
+            let mounts = parse_mountstats(V4_1).unwrap();
+            assert_eq!(mounts.len(), 1);
+            let nas = &mounts[0];
+            assert_eq!(nas.mountpoint, "/net/nas");
+            assert_eq!(nas.server, "nas.example.com");
+            assert_eq!(nas.export, "/");
+            assert_eq!(nas.version, "4.1");
+            assert_eq!(nas.ops.len(), 59);
+            assert_eq!(op(nas, "SEQUENCE").ops, 180);
+            let read = op(nas, "READ");
+            assert_eq!(read.bytes_received, 1258444800);
+            assert_eq!(read.errors, None);
+        }
+
+        #[test]
+        fn v4_2() {
+            let mounts = parse_mountstats(V4_2).unwrap();
+            assert_eq!(mounts.len(), 1);
+            let build = &mounts[0];
+            assert_eq!(build.mountpoint, "/srv/build");
+            assert_eq!(build.server, "2001:db8::20");
+            assert_eq!(build.export, "/projects/build");
+            assert_eq!(build.version, "4.2");
+            assert_eq!(build.bytes.server.read, 110100480);
+            assert_eq!(build.ops.len(), 69);
+            assert_eq!(op(build, "COPY").ops, 4);
+            assert_eq!(op(build, "READ_PLUS").execute_ms, 1902);
+            assert_eq!(op(build, "READ_PLUS").errors, Some(0));
+            assert_eq!(op(build, "GETXATTR").errors, Some(5));
+        }
+
+        #[test]
+        fn no_nfs() {
+            let text = "device proc mounted on /proc with fstype proc\n";
+            assert_eq!(parse_mountstats(text).unwrap(), vec![]);
+        }
+
+        #[test]
+        fn malformed() {
+            let text = concat!(
+                "device h:/e mounted on /m with fstype nfs statvers=1.1\n",
+                "\tper-op statistics\n",
+                "\t        READ: 1 2 3\n",
+            );
+            let e = parse_mountstats(text).unwrap_err();
+            assert_eq!(e.to_string(), "malformed READ line");
+            let e = parse_mountstats("device h:/e mounted\n").unwrap_err();
+            assert_eq!(e.to_string(), "malformed device line");
+        }
+
+        #[test]
+        fn unescape() {
+            assert_eq!(super::unescape(r"/a\040b\011c"), "/a b\tc");
+            assert_eq!(super::unescape(r"/a\b\9"), r"/a\b\9");
+        }
+    }
+}
@@ -1,43 +1,54 @@
 // vim: tw=80
 
+#[cfg(target_os = "freebsd")]
+use std::time::SystemTime;
 use std::{
     net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
     process::exit,
-    sync::{Arc, LazyLock, Mutex},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use axum::{
     Router,
     extract::{ConnectInfo, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Response},
     routing::get,
 };
+#[cfg(target_os = "freebsd")]
 use capsicum::casper::Casper;
-use clap::{CommandFactory, Parser, crate_version};
+use clap::{Parser, Subcommand, crate_version};
+use collector::SharedSource;
 use env_logger::{Builder, Env};
-use prometheus::{
-    Gauge,
-    GaugeVec,
-    TextEncoder,
-    register_gauge,
-    register_gauge_vec,
-};
+use prometheus::{Encoder, Gauge, ProtobufEncoder, Registry, TextEncoder};
+use source::StatsSource;
 use tokio::net::TcpListener;
 
+#[cfg(target_os = "freebsd")]
 mod cap_nfs;
+mod collector;
+mod counters;
+mod exposition;
 #[cfg(target_pointer_width = "64")]
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
 mod ffi64;
+mod layouts;
+mod linux;
 mod nfs;
+mod source;
+mod trace;
+mod watchdog;
 #[cfg(target_pointer_width = "64")]
 use ffi64 as ffi;
 #[cfg(target_pointer_width = "32")]
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
 mod ffi32;
-use cap_nfs::{CapNfsAgent, CasperExt};
+#[cfg(target_os = "freebsd")]
+use cap_nfs::CasperExt;
 #[cfg(target_pointer_width = "32")]
 use ffi32 as ffi;
 
@@ -47,23 +58,114 @@ use ffi32 as ffi;
 struct Cli {
     /// Bind to this local address
     #[clap(short = 'b', long, default_value = "0.0.0.0", value_name = "ADDR")]
-    bind:   String,
+    bind:               String,
     /// Publish NFS client statistics
     #[clap(short = 'c')]
-    client: bool,
+    client:             bool,
     /// Publish NFS server statistics
     #[clap(short = 's')]
-    server: bool,
+    server:             bool,
+    /// Read statistics from this raw image of the kernel's struct nfsstatsv1,
+    /// rather than from the kernel.  It's reread for every scrape.
+    #[clap(long, value_name = "PATH")]
+    fixture:            Option<PathBuf>,
+    /// Don't use Capsicum.  Collect statistics directly from the kernel,
+    /// rather than through a Casper helper.
+    #[clap(long)]
+    no_capsicum:        bool,
+    /// Keep nfsd's counters monotonic across nfsd restarts, by adding their
+    /// values from before each restart.  Adjusted counters are labeled
+    /// reset_adjusted="true".
+    #[clap(long)]
+    monotonic_counters: bool,
+    /// Publish metrics with these names.  v2 follows Prometheus's naming
+    /// conventions, and "both" publishes v1 and v2 names side by side.
+    #[clap(long, value_enum, default_value = "v1", value_name = "SCHEMA")]
+    metric_schema:      collector::Schema,
+    /// Also publish server statistics under node_exporter's node_nfsd_*
+    /// names, so dashboards can be shared with Linux servers.
+    #[clap(long)]
+    node_compat:        bool,
     /// TCP port
     #[clap(short = 'p', long, default_value = "9898")]
-    port:   u16,
+    port:               u16,
+    /// Warn if nfsd goes this many seconds without completing an operation
+    #[clap(long, default_value = "60", value_name = "SECONDS")]
+    stall_threshold:    u64,
+    /// Check for nfsd stalls this often, in seconds
+    #[clap(
+        long,
+        default_value = "10",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    watchdog_interval:  u64,
+    #[clap(subcommand)]
+    command:            Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Append raw snapshots of the kernel's statistics to a trace file, for
+    /// later replay.  Runs until killed.
+    Record {
+        /// Take a snapshot this often, like "10s", "500ms", or "5m"
+        #[clap(
+            long,
+            default_value = "10s",
+            value_name = "DURATION",
+            value_parser = parse_interval
+        )]
+        interval: Duration,
+        /// Append to this trace file, creating it if necessary
+        #[clap(long, value_name = "PATH")]
+        out:      PathBuf,
+    },
+    /// Serve statistics from a trace file made by the record command, rather
+    /// than from the kernel.
+    Replay {
+        /// The trace file
+        #[clap(value_name = "PATH")]
+        trace: PathBuf,
+        /// Replay this many times faster than the trace was recorded
+        #[clap(long, default_value = "1", value_parser = parse_speed)]
+        speed: f64,
+    },
+}
+
+/// Parse a duration like "10s", "500ms", "5m", or "1h".  A bare number is in
+/// seconds.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(digits);
+    let n: u64 = n.parse().map_err(|_| format!("invalid duration {s:?}"))?;
+    let d = match unit {
+        "ms" => Duration::from_millis(n),
+        "" | "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n.saturating_mul(60)),
+        "h" => Duration::from_secs(n.saturating_mul(3600)),
+        _ => return Err(format!("unknown unit {unit:?} in duration {s:?}")),
+    };
+    if d.is_zero() {
+        return Err("duration must be greater than zero".to_string());
+    }
+    Ok(d)
+}
+
+/// Parse a replay speed factor
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("invalid speed {s:?}")),
+    }
 }
 
 struct AppState {
-    server:        bool,
-    // Annoyingly, axum requires AppState to be Send, even though we're using a
-    // single-threaded tokio runtime.  So we have to wrap it in a silly Mutex.
-    cap_nfs_agent: Mutex<CapNfsAgent>,
+    /// Every metric published by the exporter.  It's private, so no other
+    /// library can add its own metrics.
+    registry: Registry,
+    /// Start times of the collector's counters, for OpenMetrics
+    created:  collector::Created,
 }
 
 /// Wrapper type that implements IntoResponse for anyhow::Error
@@ -83,288 +185,337 @@ impl IntoResponse for AppError {
     }
 }
 
-// Create metrics
-// Even though these are really counters, we use the Gauge API since the
-// kernel reports their current values and prometheus::Counter only has an
-// inc method, not a set method.
-// And even though they're integers, we must use the f64 gauge type because
-// prometheus::IntCounter wraps i64 instead of u64.  The loss of precision
-// is unavoidable because Prometheus itself treats all metrics as f64
-// anyway.
-static BYTES: LazyLock<GaugeVec> = LazyLock::new(|| {
-    register_gauge_vec!(
-        "nfs_nfsd_total_bytes",
-        "Total nfsd bytes per operation",
-        &["method"]
-    )
-    .expect("cannot create gauge")
-});
-static DURATION: LazyLock<GaugeVec> = LazyLock::new(|| {
-    register_gauge_vec!(
-        "nfs_nfsd_total_duration",
-        "Total nfsd nanoseconds spend processing each operation.  May wrap.",
-        &["method"]
-    )
-    .expect("cannot create gauge")
-});
-static RPCS: LazyLock<GaugeVec> = LazyLock::new(|| {
-    register_gauge_vec!(
-        "nfs_nfsd_requests_total",
-        "Count of server RPCs",
-        &["method"]
-    )
-    .expect("cannot create gauge")
-});
-static STARTCNT: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_start_count",
-        "Total number of opreations started since boot"
-    )
-    .expect("cannot create gauge")
-});
-static DONECNT: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_done_count",
-        "Total number of opreations completed since boot"
-    )
-    .expect("cannot create gauge")
-});
-static BUSYTIME: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_busytime",
-        "Total time in ns that nfsd was busy with at least one opeartion"
-    )
-    .expect("cannot create gauge")
-});
-static CACHE_INPROG: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_cache_in_progress_hits",
-        "Server cache in-progress hits"
-    )
-    .expect("cannot create gauge")
-});
-// Don't publish Idem.  It's always 0
-static CACHE_NONIDEMPOTENT: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_cache_nonidempotent_hits",
-        "Server cache non-idempotent hits"
-    )
-    .expect("cannot create gauge")
-});
-static CACHE_MISSES: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_server_cache_misses", "Server cache misses")
-        .expect("cannot create gauge")
-});
-static CACHE_SIZE: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_server_cache_size",
-        "Server cache size in entries"
-    )
-    .expect("cannot create gauge")
-});
-static CACHE_TCPPEAK: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_server_cache_tcp_peak",
-        "Peak size of the NFS server's TCP client cache"
-    )
-    .expect("cannot create gauge")
-});
-static CLIENTS: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_clients", "Number of connected NFS v4.x clients")
-        .expect("cannot create gauge")
-});
-static DELEGS: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_delegations", "Number of active NFS delegations")
-        .expect("cannot create gauge")
-});
-static LOCK_OWNER: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_lock_owners", "Number of active NFS lock owners")
-        .expect("cannot create gauge")
-});
-static LOCKS: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_locks", "Number of active NFS locks")
-        .expect("cannot create gauge")
-});
-static OPEN_OWNER: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!(
-        "nfs_nfsd_open_owners",
-        "Number of active NFS v4.0 Open Owners"
-    )
-    .expect("cannot create gauge")
-});
-static OPENS: LazyLock<Gauge> = LazyLock::new(|| {
-    register_gauge!("nfs_nfsd_opens", "Number of NFS v4.x open files?")
-        .expect("cannot create gauge")
-});
-
 async fn metrics(
     addr: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     state: State<Arc<AppState>>,
-) -> Result<String, AppError> {
+) -> Result<Response, AppError> {
     let ip = addr.ip();
     log::debug!("Servicing request from {ip}");
 
-    let nfs_stat = state.cap_nfs_agent.lock().unwrap().nfsstat().unwrap();
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let metric_families = state.registry.gather();
+    let (content_type, body) = match exposition::negotiate(accept) {
+        exposition::Format::OpenMetrics => {
+            let created = state.created.lock().unwrap();
+            let body =
+                exposition::encode_openmetrics(&metric_families, &created);
+            (exposition::OPENMETRICS_FORMAT, body.into_bytes())
+        }
+        exposition::Format::Text => {
+            let encoder = TextEncoder::new();
+            let body = encoder
+                .encode_to_string(&metric_families)
+                .map_err(anyhow::Error::from)?;
+            (prometheus::TEXT_FORMAT, body.into_bytes())
+        }
+        exposition::Format::Protobuf => {
+            let encoder = ProtobufEncoder::new();
+            let mut body = Vec::new();
+            encoder
+                .encode(&metric_families, &mut body)
+                .map_err(anyhow::Error::from)?;
+            (prometheus::PROTOBUF_FORMAT, body)
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
 
-    if state.server {
-        macro_rules! set_rpcs {
-            ($label:ident, $field:ident) => {
-                RPCS.with_label_values(&[stringify!($label)])
-                    .set(nfs_stat.server_rpcs.$field as f64);
-            };
+/// Periodically check whether nfsd has stopped completing operations.  The
+/// gauges are registered only once the source has reported nfsd's progress,
+/// so sources that can't are left without them.
+async fn watchdog(
+    source: SharedSource,
+    interval: Duration,
+    threshold: Duration,
+    registry: Registry,
+    since_last_completion: Gauge,
+    stalled: Gauge,
+) {
+    let mut registered = false;
+    let mut watchdog = watchdog::Watchdog::new(threshold, Instant::now());
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let r = source.lock().unwrap().nfsstat();
+        let nfs_stat = match r {
+            Ok(nfs_stat) => nfs_stat,
+            Err(e) => {
+                log::error!("Watchdog cannot collect NFS stats: {e}");
+                continue;
+            }
+        };
+        let (Some(donecnt), Some(in_flight)) =
+            (nfs_stat.donecnt, nfs_stat.in_flight())
+        else {
+            // This OS doesn't report nfsd's progress
+            continue;
+        };
+        let status = watchdog.sample(donecnt, in_flight, Instant::now());
+        since_last_completion.set(status.since_last_completion.as_secs_f64());
+        stalled.set(if status.stalled { 1.0 } else { 0.0 });
+        if !registered {
+            for gauge in [&since_last_completion, &stalled] {
+                registry
+                    .register(Box::new(gauge.clone()))
+                    .expect("cannot register gauge");
+            }
+            registered = true;
         }
+    }
+}
 
-        BYTES
-            .with_label_values(&["Read"])
-            .set(nfs_stat.bytes.read as f64);
-        BYTES
-            .with_label_values(&["Write"])
-            .set(nfs_stat.bytes.write as f64);
-        DURATION
-            .with_label_values(&["Read"])
-            .set(nfs_stat.duration.read as f64);
-        DURATION
-            .with_label_values(&["Write"])
-            .set(nfs_stat.duration.write as f64);
-        DURATION
-            .with_label_values(&["Commit"])
-            .set(nfs_stat.duration.commit as f64);
-        STARTCNT.set(nfs_stat.startcnt as f64);
-        DONECNT.set(nfs_stat.donecnt as f64);
-        BUSYTIME.set(nfs_stat.busytime as f64);
-
-        CACHE_INPROG.set(nfs_stat.server_cache.inprog as f64);
-        CACHE_NONIDEMPOTENT.set(nfs_stat.server_cache.nonidem as f64);
-        CACHE_MISSES.set(nfs_stat.server_cache.misses as f64);
-        CACHE_SIZE.set(nfs_stat.server_cache.size as f64);
-        CACHE_TCPPEAK.set(nfs_stat.server_cache.tcp_peak as f64);
-
-        CLIENTS.set(nfs_stat.server_misc.clients as f64);
-        DELEGS.set(nfs_stat.server_misc.delegs as f64);
-        LOCK_OWNER.set(nfs_stat.server_misc.lock_owner as f64);
-        LOCKS.set(nfs_stat.server_misc.locks as f64);
-        OPEN_OWNER.set(nfs_stat.server_misc.open_owner as f64);
-        OPENS.set(nfs_stat.server_misc.opens as f64);
-
-        set_rpcs!(Access, access);
-        set_rpcs!(BackChannelCtl, backchannelctrl);
-        set_rpcs!(BindConnToSess, bindconntosess);
-        set_rpcs!(Close, close);
-        set_rpcs!(Commit, commit);
-        set_rpcs!(Create, v3create);
-        set_rpcs!(CreateSession, createsess);
-        set_rpcs!(CreateV4, create);
-        set_rpcs!(DelegPurge, delegpurge);
-        set_rpcs!(DelegReturn, delegreturn);
-        set_rpcs!(DestroyClientId, destroyclid);
-        set_rpcs!(DestroySession, destroysess);
-        set_rpcs!(ExchangeId, exchangeid);
-        set_rpcs!(FreeStateId, freestateid);
-        set_rpcs!(FsInfo, fsinfo);
-        set_rpcs!(FsStat, fsstat);
-        set_rpcs!(GetAttr, getattr);
-        set_rpcs!(GetDevInfo, getdevinfo);
-        set_rpcs!(GetDevList, getdevlist);
-        set_rpcs!(GetDirDeleg, getdirdeleg);
-        set_rpcs!(GetFH, getfh);
-        set_rpcs!(LayoutCommit, layoutcommit);
-        set_rpcs!(LayoutGet, layoutget);
-        set_rpcs!(LayoutReturn, layoutreturn);
-        set_rpcs!(Link, link);
-        set_rpcs!(Lock, lock);
-        set_rpcs!(LockT, lockt);
-        set_rpcs!(LockU, locku);
-        set_rpcs!(Lookup, lookup);
-        set_rpcs!(LookupP, lookupp);
-        set_rpcs!(MkDir, mkdir);
-        set_rpcs!(MkNod, mknod);
-        set_rpcs!(Nverify, nverify);
-        set_rpcs!(Open, open);
-        set_rpcs!(OpenAttr, openattr);
-        set_rpcs!(OpenConfirm, openconfirm);
-        set_rpcs!(OpenDgrd, opendgrd);
-        set_rpcs!(PathConf, pathconf);
-        set_rpcs!(PutFH, putfh);
-        set_rpcs!(Read, read);
-        set_rpcs!(ReadDir, readdir);
-        set_rpcs!(ReadDirPlus, readdirplus);
-        set_rpcs!(ReadLink, readlink);
-        set_rpcs!(ReclaimCompl, reclaimcompl);
-        set_rpcs!(RelLockOwner, rellckown);
-        set_rpcs!(Remove, remove);
-        set_rpcs!(Rename, rename);
-        set_rpcs!(Renew, renew);
-        set_rpcs!(RestoreFH, restorefh);
-        set_rpcs!(RmDir, rmdir);
-        set_rpcs!(SaveFH, savefh);
-        set_rpcs!(SecInfo, secinfo);
-        set_rpcs!(SecInfoNoName, secinfononame);
-        set_rpcs!(Sequence, sequence);
-        set_rpcs!(SetAttr, setattr);
-        set_rpcs!(SetClientId, setclid);
-        set_rpcs!(SetClientIdConfirm, setclidcf);
-        set_rpcs!(SetSSV, setssv);
-        set_rpcs!(SymLink, symlink);
-        set_rpcs!(TestStateId, teststateid);
-        set_rpcs!(Verify, verify);
-        set_rpcs!(WantDeleg, wantdeleg);
-        set_rpcs!(Write, write);
+/// Choose how to collect statistics from the kernel.  FreeBSD always collects
+/// client and server statistics together.
+#[cfg(target_os = "freebsd")]
+fn kernel_source(
+    capsicum: bool,
+    _client: bool,
+    _server: bool,
+    _server_required: bool,
+) -> Box<dyn StatsSource> {
+    if capsicum {
+        // Start Casper .  Safe because we're still single-threaded.
+        let mut casper = unsafe { Casper::new().unwrap() };
+        Box::new(casper.nfsstat().unwrap())
+    } else {
+        Box::new(source::Kernel::default())
     }
-    let metric_families = prometheus::gather();
-    let encoder = TextEncoder::new();
-    let body = encoder
-        .encode_to_string(&metric_families)
-        .map_err(anyhow::Error::from)?;
-    Ok(body)
+}
+
+/// Choose how to collect statistics from the kernel.  Linux has no Capsicum,
+/// and only reads the statistics that will be published.  That way a client
+/// needn't have nfsd loaded, unless server statistics are `server_required`.
+#[cfg(target_os = "linux")]
+fn kernel_source(
+    _capsicum: bool,
+    client: bool,
+    server: bool,
+    server_required: bool,
+) -> Box<dyn StatsSource> {
+    Box::new(source::Procfs::new(client, server, server_required))
+}
+
+#[cfg(not(any(target_os = "freebsd", target_os = "linux")))]
+fn kernel_source(
+    _capsicum: bool,
+    _client: bool,
+    _server: bool,
+    _server_required: bool,
+) -> Box<dyn StatsSource> {
+    eprintln!("Kernel NFS statistics are only available on FreeBSD and Linux.");
+    eprintln!("Use --fixture to read them from a file instead.");
+    exit(1);
+}
+
+/// Enter capability mode.  There's nothing to enter except on FreeBSD.
+#[cfg(target_os = "freebsd")]
+fn enter_capability_mode() {
+    capsicum::enter().unwrap();
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn enter_capability_mode() {}
+
+/// Append raw snapshots of the kernel's statistics to a trace, until killed.
+#[cfg(target_os = "freebsd")]
+async fn record(interval: Duration, out: &Path) {
+    let mut writer = trace::Writer::append(out).unwrap_or_else(|e| {
+        eprintln!("Cannot open {}: {e}", out.display());
+        exit(1);
+    });
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let snapshot = match nfs::snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                log::error!("Cannot collect NFS stats: {e}");
+                continue;
+            }
+        };
+        let timestamp_ns = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let record = trace::Record {
+            timestamp_ns,
+            snapshot,
+        };
+        if let Err(e) = writer.write(&record) {
+            eprintln!("Cannot write to {}: {e}", out.display());
+            exit(1);
+        }
+    }
+}
+
+#[cfg(not(target_os = "freebsd"))]
+async fn record(_interval: Duration, _out: &Path) {
+    eprintln!("Kernel NFS statistics can only be recorded on FreeBSD.");
+    exit(1);
+}
+
+/// Load a trace for replay
+fn replay_source(path: &Path, speed: f64) -> Box<dyn StatsSource> {
+    let records = trace::read(path).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {e}", path.display());
+        exit(1);
+    });
+    let (Some(first), Some(last)) = (records.first(), records.last()) else {
+        eprintln!("{} contains no snapshots", path.display());
+        exit(1);
+    };
+    let secs =
+        last.timestamp_ns.saturating_sub(first.timestamp_ns) / 1_000_000_000;
+    log::info!(
+        "Replaying {} snapshots spanning {secs} seconds from {}",
+        records.len(),
+        path.display()
+    );
+    Box::new(source::Replay::new(records, speed, Instant::now()))
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let cli = Cli::parse();
-    let (_c, s) = if !cli.client && !cli.server {
+    let (c, s) = if !cli.client && !cli.server {
         // By default, print everything
         (true, true)
-    } else if cli.server {
-        (false, true)
     } else {
-        Cli::command()
-            .error(
-                clap::error::ErrorKind::InvalidValue,
-                "client stats are TODO",
-            )
-            .exit()
+        (cli.client, cli.server)
     };
 
     Builder::from_env(Env::default().default_filter_or("info")).init();
 
+    // Before starting Casper, so its helper inherits the disposition too.
+    #[cfg(target_os = "freebsd")]
+    nfs::ignore_sigsys();
+
     // Parse address used to bind exporter to.
     let ia: IpAddr = cli.bind.parse().unwrap();
     let sa = SocketAddr::new(ia, cli.port);
 
-    // Start Casper .  Safe because we're still single-threaded.
-    let mut casper = unsafe { Casper::new().unwrap() };
-    let cap_nfs_agent = Mutex::new(casper.nfsstat().unwrap());
+    // Fixtures must be reread from the file system, so they can't use
+    // capability mode.  Traces are read entirely at startup, so they can.
+    let (source, sandbox) = match (&cli.command, &cli.fixture) {
+        (Some(Command::Record { interval, out }), _) => {
+            return record(*interval, out).await;
+        }
+        (Some(Command::Replay { .. }), Some(_)) => {
+            eprintln!("--fixture cannot be used with replay");
+            exit(1);
+        }
+        (Some(Command::Replay { trace, speed }), None) => {
+            (replay_source(trace, *speed), !cli.no_capsicum)
+        }
+        (None, Some(path)) => {
+            let source: Box<dyn StatsSource> =
+                Box::new(source::Fixture::new(path.clone()));
+            (source, false)
+        }
+        (None, None) => {
+            let source = kernel_source(!cli.no_capsicum, c, s, cli.server);
+            (source, !cli.no_capsicum)
+        }
+    };
 
     let listener = TcpListener::bind(sa).await.unwrap_or_else(|e| {
         eprintln!("Error starting exporter: {e}");
         exit(1);
     });
 
-    let state = AppState {
-        server: s,
-        cap_nfs_agent,
+    // Annoyingly, prometheus requires collectors to be Send and Sync, even
+    // though we're using a single-threaded tokio runtime.  So we have to wrap
+    // the source in a silly Mutex.
+    let source: SharedSource = Arc::new(Mutex::new(source));
+    let registry = Registry::new();
+    let options = collector::Options {
+        client:      c,
+        server:      s,
+        schema:      cli.metric_schema,
+        node_compat: cli.node_compat,
+        monotonic:   cli.monotonic_counters,
     };
+    let collector = collector::NfsCollector::new(source.clone(), options);
+    let created = collector.created();
+    registry
+        .register(Box::new(collector))
+        .expect("cannot register collector");
+    let state = AppState { registry, created };
 
-    // Enter capability mode.
-    capsicum::enter().unwrap();
+    if sandbox {
+        enter_capability_mode();
+    }
+
+    // Annoyingly, with_state requires its argument to be `Send` even if
+    // we're using a single-threaded runtime.  So we must use Arc instead of
+    // Rc.
+    let state = Arc::new(state);
+
+    // The watchdog times stalls by the wall clock, which a replay at any
+    // other speed than the trace's would distort.
+    let replaying = matches!(cli.command, Some(Command::Replay { .. }));
+    if s && !replaying {
+        let since_last_completion = Gauge::new(
+            "nfs_nfsd_seconds_since_last_completion",
+            concat!(
+                "Seconds since nfsd last completed an operation, while ",
+                "operations were in flight.  0 if idle."
+            ),
+        )
+        .expect("cannot create gauge");
+        let stalled = Gauge::new(
+            "nfs_nfsd_stalled",
+            concat!(
+                "1 if nfsd has exceeded the stall threshold without ",
+                "completing an operation, else 0"
+            ),
+        )
+        .expect("cannot create gauge");
+        tokio::spawn(watchdog(
+            source,
+            Duration::from_secs(cli.watchdog_interval),
+            Duration::from_secs(cli.stall_threshold),
+            state.registry.clone(),
+            since_last_completion,
+            stalled,
+        ));
+    }
 
     let app = Router::new()
         .route("/metrics", get(metrics))
-        // Annoyingly, with_state requires its argument to be `Send` even if
-        // we're using a single-threaded runtime.  So we must use Arc instead of
-        // Rc.
-        .with_state(Arc::new(state))
+        .with_state(state)
         .into_make_service_with_connect_info::<SocketAddr>();
 
     axum::serve(listener, app).await.unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod parse_interval {
+        use super::*;
+
+        #[test]
+        fn units() {
+            assert_eq!(parse_interval("10"), Ok(Duration::from_secs(10)));
+            assert_eq!(parse_interval("10s"), Ok(Duration::from_secs(10)));
+            assert_eq!(parse_interval("500ms"), Ok(Duration::from_millis(500)));
+            assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+            assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(7200)));
+        }
+
+        #[test]
+        fn invalid() {
+            assert!(parse_interval("").is_err());
+            assert!(parse_interval("s").is_err());
+            assert!(parse_interval("10d").is_err());
+            assert!(parse_interval("-1s").is_err());
+            assert!(parse_interval("0s").is_err());
+        }
+    }
+}
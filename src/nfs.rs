@@ -1,11 +1,63 @@
+#[cfg(target_os = "freebsd")]
+use std::{ffi::CStr, io::ErrorKind, os::raw::c_void};
 use std::{
     io::{Error, Result},
-    os::raw::c_void,
+    time::{Duration, SystemTime},
 };
 
 use bincode_next::{Decode, Encode};
 
-use crate::ffi;
+use crate::{
+    ffi,
+    layouts::{self, KernelStruct},
+};
+
+/// Read an integer sysctl.  Returns `None` if it doesn't exist.
+#[cfg(target_os = "freebsd")]
+fn sysctl_int(name: &CStr) -> Result<Option<u64>> {
+    let mut value: std::os::raw::c_int = 0;
+    let mut len = std::mem::size_of_val(&value);
+    let r = unsafe {
+        ffi::sysctlbyname(
+            name.as_ptr(),
+            &mut value as *mut _ as *mut c_void,
+            &mut len,
+            std::ptr::null(),
+            0,
+        )
+    };
+    if r != 0 {
+        let e = Error::last_os_error();
+        return match e.kind() {
+            ErrorKind::NotFound => Ok(None),
+            _ => Err(e),
+        };
+    }
+    Ok(Some(i64::max(0, i64::from(value)) as u64))
+}
+
+/// Current time since boot, in nanoseconds.  The same clock as the kernel's
+/// `binuptime`.
+fn uptime_ns() -> Result<u64> {
+    let mut ts = libc::timespec {
+        tv_sec:  0,
+        tv_nsec: 0,
+    };
+    // On FreeBSD, CLOCK_MONOTONIC counts from boot, just like binuptime.
+    let r = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    if r != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok((ts.tv_sec as u64)
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(ts.tv_nsec as u64))
+}
+
+/// When the system booted, according to the same clock as [`uptime_ns`]
+pub fn boot_time() -> Result<SystemTime> {
+    let uptime = Duration::from_nanos(uptime_ns()?);
+    Ok(SystemTime::now() - uptime)
+}
 
 fn bintime_to_ns(bintime: &ffi::bintime) -> u64 {
     (bintime.sec as u64)
@@ -19,91 +71,415 @@ pub struct PerRW {
     pub write: u64,
 }
 
+/// Number of bits in the kernel's `time_t`
+const TIME_T_BITS: u32 = std::mem::size_of::<ffi::time_t>() as u32 * 8;
+
+/// A duration as recorded by the kernel's `struct bintime`.
+///
+/// The kernel accumulates durations in a `bintime` whose seconds field is a
+/// `time_t`.  That's only 32 bits wide on some architectures, so a busy
+/// server's cumulative durations can wrap.  See
+/// [`crate::counters::WrapState`].
 #[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
-pub struct PerRWC {
-    pub read:   u64,
-    pub write:  u64,
-    pub commit: u64,
+pub struct Bintime {
+    /// Whole seconds, modulo [`Bintime::MODULUS`]
+    pub sec:  u64,
+    /// Fractions of a second, in units of 2^-64 s
+    pub frac: u64,
+}
+
+impl Bintime {
+    /// The number of seconds at which `sec` wraps around to 0
+    pub const MODULUS: f64 = (1u128 << TIME_T_BITS) as f64;
+
+    /// Convert to seconds, without any loss of range
+    pub fn as_secs_f64(&self) -> f64 {
+        self.sec as f64 + self.frac as f64 / (1u128 << 64) as f64
+    }
+}
+
+impl From<&ffi::bintime> for Bintime {
+    fn from(bt: &ffi::bintime) -> Self {
+        // Reinterpret the signed time_t as unsigned, modulo its own width.
+        let sec = (bt.sec as i128 as u128) % (1u128 << TIME_T_BITS);
+        Bintime {
+            sec:  sec as u64,
+            frac: bt.frac,
+        }
+    }
+}
+
+/// NFS protocol version that introduced an operation
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Version {
+    V2,
+    V3,
+    V4_0,
+    V4_1,
+    V4_2,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::V2 => "2",
+            Version::V3 => "3",
+            Version::V4_0 => "4.0",
+            Version::V4_1 => "4.1",
+            Version::V4_2 => "4.2",
+        }
+    }
+}
+
+/// Broad classification of NFS operations
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Category {
+    /// Operations that read or write file contents
+    Data,
+    /// pNFS layout and device operations
+    Layout,
+    /// Operations on the file system namespace and file attributes
+    Metadata,
+    /// NFS v4.1+ session and client ID management
+    Session,
+    /// NFS v4 open, lock, and delegation state
+    State,
 }
 
-/// Counts of every RPC processed
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Data => "data",
+            Category::Layout => "layout",
+            Category::Metadata => "metadata",
+            Category::Session => "session",
+            Category::State => "state",
+        }
+    }
+}
+
+/// An NFS operation counted by the kernel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Op {
+    /// Index into the kernel's per-operation arrays.  An `NFSV4OP_*` constant.
+    pub idx:      u32,
+    /// Name of the operation, as used in the `method` label.
+    pub name:     &'static str,
+    pub version:  Version,
+    pub category: Category,
+}
+
+impl Op {
+    const fn new(
+        idx: u32,
+        name: &'static str,
+        version: Version,
+        category: Category,
+    ) -> Self {
+        Op {
+            idx,
+            name,
+            version,
+            category,
+        }
+    }
+}
+
+/// Every operation counted by the NFS server.
+///
+/// NFS v2 and v3 RPCs are counted together with their v4 equivalents.  The
+/// v3-only RPCs are counted using fake operation numbers, starting at
+/// `NFSV42_NOPS`.
+pub const SERVER_OPS: &[Op] = {
+    use Category::*;
+    use Version::*;
+    &[
+        Op::new(ffi::NFSV4OP_ACCESS, "Access", V3, Metadata),
+        Op::new(ffi::NFSV4OP_CLOSE, "Close", V4_0, State),
+        Op::new(ffi::NFSV4OP_COMMIT, "Commit", V3, Data),
+        Op::new(ffi::NFSV4OP_CREATE, "CreateV4", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_DELEGPURGE, "DelegPurge", V4_0, State),
+        Op::new(ffi::NFSV4OP_DELEGRETURN, "DelegReturn", V4_0, State),
+        Op::new(ffi::NFSV4OP_GETATTR, "GetAttr", V2, Metadata),
+        Op::new(ffi::NFSV4OP_GETFH, "GetFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_LINK, "Link", V2, Metadata),
+        Op::new(ffi::NFSV4OP_LOCK, "Lock", V4_0, State),
+        Op::new(ffi::NFSV4OP_LOCKT, "LockT", V4_0, State),
+        Op::new(ffi::NFSV4OP_LOCKU, "LockU", V4_0, State),
+        Op::new(ffi::NFSV4OP_LOOKUP, "Lookup", V2, Metadata),
+        Op::new(ffi::NFSV4OP_LOOKUPP, "LookupP", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_NVERIFY, "Nverify", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_OPEN, "Open", V4_0, State),
+        Op::new(ffi::NFSV4OP_OPENATTR, "OpenAttr", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_OPENCONFIRM, "OpenConfirm", V4_0, State),
+        Op::new(ffi::NFSV4OP_OPENDOWNGRADE, "OpenDgrd", V4_0, State),
+        Op::new(ffi::NFSV4OP_PUTFH, "PutFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_PUTPUBFH, "PutPubFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_PUTROOTFH, "PutRootFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_READ, "Read", V2, Data),
+        Op::new(ffi::NFSV4OP_READDIR, "ReadDir", V2, Metadata),
+        Op::new(ffi::NFSV4OP_READLINK, "ReadLink", V2, Metadata),
+        Op::new(ffi::NFSV4OP_REMOVE, "Remove", V2, Metadata),
+        Op::new(ffi::NFSV4OP_RENAME, "Rename", V2, Metadata),
+        Op::new(ffi::NFSV4OP_RENEW, "Renew", V4_0, State),
+        Op::new(ffi::NFSV4OP_RESTOREFH, "RestoreFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_SAVEFH, "SaveFH", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_SECINFO, "SecInfo", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_SETATTR, "SetAttr", V2, Metadata),
+        Op::new(ffi::NFSV4OP_SETCLIENTID, "SetClientId", V4_0, State),
+        Op::new(
+            ffi::NFSV4OP_SETCLIENTIDCFRM,
+            "SetClientIdConfirm",
+            V4_0,
+            State,
+        ),
+        Op::new(ffi::NFSV4OP_VERIFY, "Verify", V4_0, Metadata),
+        Op::new(ffi::NFSV4OP_WRITE, "Write", V2, Data),
+        Op::new(ffi::NFSV4OP_RELEASELCKOWN, "RelLockOwner", V4_0, State),
+        Op::new(ffi::NFSV4OP_BACKCHANNELCTL, "BackChannelCtl", V4_1, Session),
+        Op::new(ffi::NFSV4OP_BINDCONNTOSESS, "BindConnToSess", V4_1, Session),
+        Op::new(ffi::NFSV4OP_EXCHANGEID, "ExchangeId", V4_1, Session),
+        Op::new(ffi::NFSV4OP_CREATESESSION, "CreateSession", V4_1, Session),
+        Op::new(ffi::NFSV4OP_DESTROYSESSION, "DestroySession", V4_1, Session),
+        Op::new(ffi::NFSV4OP_FREESTATEID, "FreeStateId", V4_1, State),
+        Op::new(ffi::NFSV4OP_GETDIRDELEG, "GetDirDeleg", V4_1, State),
+        Op::new(ffi::NFSV4OP_GETDEVINFO, "GetDevInfo", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_GETDEVLIST, "GetDevList", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_LAYOUTCOMMIT, "LayoutCommit", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_LAYOUTGET, "LayoutGet", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_LAYOUTRETURN, "LayoutReturn", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_SECINFONONAME, "SecInfoNoName", V4_1, Metadata),
+        Op::new(ffi::NFSV4OP_SEQUENCE, "Sequence", V4_1, Session),
+        Op::new(ffi::NFSV4OP_SETSSV, "SetSSV", V4_1, Session),
+        Op::new(ffi::NFSV4OP_TESTSTATEID, "TestStateId", V4_1, State),
+        Op::new(ffi::NFSV4OP_WANTDELEG, "WantDeleg", V4_1, State),
+        Op::new(
+            ffi::NFSV4OP_DESTROYCLIENTID,
+            "DestroyClientId",
+            V4_1,
+            Session,
+        ),
+        Op::new(ffi::NFSV4OP_RECLAIMCOMPL, "ReclaimCompl", V4_1, Session),
+        Op::new(ffi::NFSV4OP_ALLOCATE, "Allocate", V4_2, Data),
+        Op::new(ffi::NFSV4OP_COPY, "Copy", V4_2, Data),
+        Op::new(ffi::NFSV4OP_COPYNOTIFY, "CopyNotify", V4_2, Data),
+        Op::new(ffi::NFSV4OP_DEALLOCATE, "Deallocate", V4_2, Data),
+        Op::new(ffi::NFSV4OP_IOADVISE, "IoAdvise", V4_2, Data),
+        Op::new(ffi::NFSV4OP_LAYOUTERROR, "LayoutError", V4_2, Layout),
+        Op::new(ffi::NFSV4OP_LAYOUTSTATS, "LayoutStats", V4_2, Layout),
+        Op::new(ffi::NFSV4OP_OFFLOADCANCEL, "OffloadCancel", V4_2, Data),
+        Op::new(ffi::NFSV4OP_OFFLOADSTATUS, "OffloadStatus", V4_2, Data),
+        Op::new(ffi::NFSV4OP_READPLUS, "ReadPlus", V4_2, Data),
+        Op::new(ffi::NFSV4OP_SEEK, "Seek", V4_2, Data),
+        Op::new(ffi::NFSV4OP_WRITESAME, "WriteSame", V4_2, Data),
+        Op::new(ffi::NFSV4OP_CLONE, "Clone", V4_2, Data),
+        Op::new(ffi::NFSV4OP_GETXATTR, "GetXattr", V4_2, Metadata),
+        Op::new(ffi::NFSV4OP_SETXATTR, "SetXattr", V4_2, Metadata),
+        Op::new(ffi::NFSV4OP_LISTXATTRS, "ListXattrs", V4_2, Metadata),
+        Op::new(ffi::NFSV4OP_REMOVEXATTR, "RemoveXattr", V4_2, Metadata),
+        Op::new(ffi::NFSV4OP_SYMLINK, "SymLink", V2, Metadata),
+        Op::new(ffi::NFSV4OP_MKDIR, "MkDir", V2, Metadata),
+        Op::new(ffi::NFSV4OP_RMDIR, "RmDir", V2, Metadata),
+        Op::new(ffi::NFSV4OP_READDIRPLUS, "ReadDirPlus", V3, Metadata),
+        Op::new(ffi::NFSV4OP_MKNOD, "MkNod", V3, Metadata),
+        Op::new(ffi::NFSV4OP_FSSTAT, "FsStat", V2, Metadata),
+        Op::new(ffi::NFSV4OP_FSINFO, "FsInfo", V3, Metadata),
+        Op::new(ffi::NFSV4OP_PATHCONF, "PathConf", V3, Metadata),
+        Op::new(ffi::NFSV4OP_V3CREATE, "Create", V2, Metadata),
+    ]
+};
+
+/// Number of entries in [`SERVER_OPS`]
+pub const SERVER_NOPS: usize = SERVER_OPS.len();
+
+/// A counter for every operation processed.  See [`SERVER_OPS`].
+#[derive(Clone, Copy, Debug, Decode, Eq, PartialEq, Encode)]
+pub struct PerRPC<T = u64>(pub [T; SERVER_NOPS]);
+
+impl<T: Copy + Default> PerRPC<T> {
+    /// Construct by looking up each operation's index in the kernel's arrays
+    pub fn from_fn<F: Fn(usize) -> T>(f: F) -> Self {
+        let mut per_rpc = Self::default();
+        for (v, op) in per_rpc.0.iter_mut().zip(SERVER_OPS) {
+            *v = f(op.idx as usize);
+        }
+        per_rpc
+    }
+
+    /// Iterate through every operation along with its count.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Op, T)> + '_ {
+        SERVER_OPS.iter().zip(self.0.iter().copied())
+    }
+}
+
+impl<T: Copy + Default> Default for PerRPC<T> {
+    fn default() -> Self {
+        Self([T::default(); SERVER_NOPS])
+    }
+}
+
+/// Every NFS v4 callback operation.
+pub const CALLBACK_OPS: &[Op] = {
+    use Category::*;
+    use Version::*;
+    &[
+        Op::new(ffi::NFSV4OP_CBGETATTR, "GetAttr", V4_0, State),
+        Op::new(ffi::NFSV4OP_CBRECALL, "Recall", V4_0, State),
+        Op::new(ffi::NFSV4OP_CBLAYOUTRECALL, "LayoutRecall", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_CBNOTIFY, "Notify", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBPUSHDELEG, "PushDeleg", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBRECALLANY, "RecallAny", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBRECALLOBJAVAIL, "RecallObjAvail", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBRECALLSLOT, "RecallSlot", V4_1, Session),
+        Op::new(ffi::NFSV4OP_CBSEQUENCE, "Sequence", V4_1, Session),
+        Op::new(ffi::NFSV4OP_CBWANTCANCELLED, "WantsCancelled", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBNOTIFYLOCK, "NotifyLock", V4_1, State),
+        Op::new(ffi::NFSV4OP_CBNOTIFYDEVID, "NotifyDevId", V4_1, Layout),
+        Op::new(ffi::NFSV4OP_CBOFFLOAD, "Offload", V4_2, Data),
+    ]
+};
+
+/// Number of entries in [`CALLBACK_OPS`]
+pub const CALLBACK_NOPS: usize = CALLBACK_OPS.len();
+
+/// Counts of every NFS v4 callback RPC.  See [`CALLBACK_OPS`].
 #[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
-pub struct PerRPC {
-    pub access:          u64,
-    pub backchannelctrl: u64,
-    pub bindconntosess:  u64,
-    pub close:           u64,
-    pub commit:          u64,
-    pub create:          u64,
-    pub createsess:      u64,
-    pub delegpurge:      u64,
-    pub delegreturn:     u64,
-    pub destroyclid:     u64,
-    pub destroysess:     u64,
-    pub exchangeid:      u64,
-    pub freestateid:     u64,
-    pub fsinfo:          u64,
-    pub fsstat:          u64,
-    pub getattr:         u64,
-    pub getdevinfo:      u64,
-    pub getdevlist:      u64,
-    pub getdirdeleg:     u64,
-    pub getfh:           u64,
-    pub layoutcommit:    u64,
-    pub layoutget:       u64,
-    pub layoutreturn:    u64,
-    pub link:            u64,
-    pub lock:            u64,
-    pub lockt:           u64,
-    pub locku:           u64,
-    pub lookup:          u64,
-    pub lookupp:         u64,
-    pub mkdir:           u64,
-    pub mknod:           u64,
-    pub nverify:         u64,
-    pub open:            u64,
-    pub openattr:        u64,
-    pub openconfirm:     u64,
-    pub opendgrd:        u64,
-    pub pathconf:        u64,
-    pub putfh:           u64,
-    pub putpubfh:        u64,
-    pub putrootfh:       u64,
-    pub read:            u64,
-    pub readdir:         u64,
-    pub readdirplus:     u64,
-    pub readlink:        u64,
-    pub reclaimcompl:    u64,
-    pub rellckown:       u64,
-    pub remove:          u64,
-    pub rename:          u64,
-    pub renew:           u64,
-    pub restorefh:       u64,
-    pub rmdir:           u64,
-    pub savefh:          u64,
-    pub secinfo:         u64,
-    pub secinfononame:   u64,
-    pub sequence:        u64,
-    pub setattr:         u64,
-    pub setclid:         u64,
-    pub setclidcf:       u64,
-    pub setssv:          u64,
-    pub symlink:         u64,
-    pub teststateid:     u64,
-    pub v3create:        u64,
-    pub verify:          u64,
-    pub wantdeleg:       u64,
-    pub write:           u64,
-}
-
-/// Server cache statistics
+pub struct CallbackRPCs(pub [u64; CALLBACK_NOPS]);
+
+impl CallbackRPCs {
+    /// Iterate through every callback operation along with its count.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Op, u64)> + '_ {
+        CALLBACK_OPS.iter().zip(self.0.iter().copied())
+    }
+}
+
+/// Number of entries in [`PerClientRPC`]
+pub const CLIENT_NRPCS: usize = ffi::NFSV42_NPROCS as usize;
+
+/// Names of the client's RPCs, keyed by their index in [`PerClientRPC`].
+///
+/// Unlike the server's counters, the client's are indexed by the kernel's
+/// `NFSPROC_*` numbering, which includes compound pseudo-operations like
+/// `Open` and pNFS data server operations like `CommitDS`.
+pub const CLIENT_RPC_NAMES: [(u32, &str); CLIENT_NRPCS] = [
+    (ffi::NFSPROC_NULL, "Null"),
+    (ffi::NFSPROC_GETATTR, "GetAttr"),
+    (ffi::NFSPROC_SETATTR, "SetAttr"),
+    (ffi::NFSPROC_LOOKUP, "Lookup"),
+    (ffi::NFSPROC_ACCESS, "Access"),
+    (ffi::NFSPROC_READLINK, "ReadLink"),
+    (ffi::NFSPROC_READ, "Read"),
+    (ffi::NFSPROC_WRITE, "Write"),
+    (ffi::NFSPROC_CREATE, "Create"),
+    (ffi::NFSPROC_MKDIR, "MkDir"),
+    (ffi::NFSPROC_SYMLINK, "SymLink"),
+    (ffi::NFSPROC_MKNOD, "MkNod"),
+    (ffi::NFSPROC_REMOVE, "Remove"),
+    (ffi::NFSPROC_RMDIR, "RmDir"),
+    (ffi::NFSPROC_RENAME, "Rename"),
+    (ffi::NFSPROC_LINK, "Link"),
+    (ffi::NFSPROC_READDIR, "ReadDir"),
+    (ffi::NFSPROC_READDIRPLUS, "ReadDirPlus"),
+    (ffi::NFSPROC_FSSTAT, "FsStat"),
+    (ffi::NFSPROC_FSINFO, "FsInfo"),
+    (ffi::NFSPROC_PATHCONF, "PathConf"),
+    (ffi::NFSPROC_COMMIT, "Commit"),
+    (ffi::NFSPROC_LOOKUPP, "LookupP"),
+    (ffi::NFSPROC_SETCLIENTID, "SetClientId"),
+    (ffi::NFSPROC_SETCLIENTIDCFRM, "SetClientIdConfirm"),
+    (ffi::NFSPROC_LOCK, "Lock"),
+    (ffi::NFSPROC_LOCKU, "LockU"),
+    (ffi::NFSPROC_OPEN, "Open"),
+    (ffi::NFSPROC_CLOSE, "Close"),
+    (ffi::NFSPROC_OPENCONFIRM, "OpenConfirm"),
+    (ffi::NFSPROC_LOCKT, "LockT"),
+    (ffi::NFSPROC_OPENDOWNGRADE, "OpenDgrd"),
+    (ffi::NFSPROC_RENEW, "Renew"),
+    (ffi::NFSPROC_PUTROOTFH, "PutRootFH"),
+    (ffi::NFSPROC_RELEASELCKOWN, "RelLockOwner"),
+    (ffi::NFSPROC_DELEGRETURN, "DelegReturn"),
+    (ffi::NFSPROC_RETDELEGREMOVE, "RetDelegRemove"),
+    (ffi::NFSPROC_RETDELEGRENAME1, "RetDelegRename1"),
+    (ffi::NFSPROC_RETDELEGRENAME2, "RetDelegRename2"),
+    (ffi::NFSPROC_GETACL, "GetACL"),
+    (ffi::NFSPROC_SETACL, "SetACL"),
+    (ffi::NFSPROC_EXCHANGEID, "ExchangeId"),
+    (ffi::NFSPROC_CREATESESSION, "CreateSession"),
+    (ffi::NFSPROC_DESTROYSESSION, "DestroySession"),
+    (ffi::NFSPROC_DESTROYCLIENT, "DestroyClientId"),
+    (ffi::NFSPROC_FREESTATEID, "FreeStateId"),
+    (ffi::NFSPROC_LAYOUTGET, "LayoutGet"),
+    (ffi::NFSPROC_GETDEVICEINFO, "GetDevInfo"),
+    (ffi::NFSPROC_LAYOUTCOMMIT, "LayoutCommit"),
+    (ffi::NFSPROC_LAYOUTRETURN, "LayoutReturn"),
+    (ffi::NFSPROC_RECLAIMCOMPL, "ReclaimCompl"),
+    (ffi::NFSPROC_WRITEDS, "WriteDS"),
+    (ffi::NFSPROC_READDS, "ReadDS"),
+    (ffi::NFSPROC_COMMITDS, "CommitDS"),
+    (ffi::NFSPROC_OPENLAYGET, "OpenLayoutGet"),
+    (ffi::NFSPROC_CREATELAYGET, "CreateLayoutGet"),
+    (ffi::NFSPROC_IOADVISE, "IoAdvise"),
+    (ffi::NFSPROC_ALLOCATE, "Allocate"),
+    (ffi::NFSPROC_COPY, "Copy"),
+    (ffi::NFSPROC_SEEK, "Seek"),
+    (ffi::NFSPROC_SEEKDS, "SeekDS"),
+    (ffi::NFSPROC_GETEXTATTR, "GetXattr"),
+    (ffi::NFSPROC_SETEXTATTR, "SetXattr"),
+    (ffi::NFSPROC_RMEXTATTR, "RemoveXattr"),
+    (ffi::NFSPROC_LISTEXTATTR, "ListXattrs"),
+    (ffi::NFSPROC_BINDCONNTOSESS, "BindConnToSess"),
+    (ffi::NFSPROC_LOOKUPOPEN, "LookupOpen"),
+    (ffi::NFSPROC_DEALLOCATE, "Deallocate"),
+    (ffi::NFSPROC_LAYOUTERROR, "LayoutError"),
+    (ffi::NFSPROC_APPENDWRITE, "AppendWrite"),
+];
+
+/// Counts of every RPC sent by the client.  See [`CLIENT_RPC_NAMES`].
+#[derive(Clone, Copy, Debug, Decode, Eq, PartialEq, Encode)]
+pub struct PerClientRPC(pub [u64; CLIENT_NRPCS]);
+
+impl Default for PerClientRPC {
+    fn default() -> Self {
+        Self([0; CLIENT_NRPCS])
+    }
+}
+
+/// Server cache statistics.  Fields are `None` if the OS doesn't report them.
 #[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
 pub struct ServerCache {
-    pub inprog:   u64,
-    pub nonidem:  u64,
+    /// Requests found in the cache, whether still in progress or not
+    pub hits:     Option<u64>,
+    pub inprog:   Option<u64>,
+    pub nonidem:  Option<u64>,
     pub misses:   u64,
-    pub size:     u64,
-    pub tcp_peak: u64,
+    /// Requests that bypassed the cache, because they're idempotent
+    pub nocache:  Option<u64>,
+    pub size:     Option<u64>,
+    pub tcp_peak: Option<u64>,
+}
+
+/// Network statistics of the NFS server
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct ServerNet {
+    /// Packets received over TCP
+    pub tcp:             u64,
+    /// TCP connections accepted
+    pub tcp_connections: u64,
+    /// Packets received over UDP
+    pub udp:             u64,
+}
+
+/// RPC layer statistics of the NFS server
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct ServerRpc {
+    /// RPCs rejected for bad authentication
+    pub bad_auth:   u64,
+    /// RPCs rejected because the client isn't allowed
+    pub bad_client: u64,
+    /// RPCs rejected because they couldn't be decoded
+    pub bad_format: u64,
+    /// Total RPCs received, including bad ones
+    pub calls:      u64,
 }
 
 /// Miscellaneous NFS server stats
@@ -112,144 +488,791 @@ pub struct ServerMisc {
     /// Number of currently connected NFS v4.0+ clients?
     pub clients:    u64,
     pub delegs:     u64,
+    /// Number of pNFS layouts issued by the server
+    pub layouts:    u64,
+    pub lock_owner: u64,
+    pub locks:      u64,
+    pub open_owner: u64,
+    pub opens:      u64,
+}
+
+/// pNFS server configuration, from sysctls.
+///
+/// The mirror level is the only pNFS state that nfsd publishes as a sysctl.
+/// The data servers, and which of their mirrors are disabled, are known only
+/// to the kernel's list of pNFS devices.  nfsd(8) fills that list in with
+/// nfssvc(2), and pnfsdskill(8) disables entries in it, but nothing reads it
+/// back out.  So neither can be exported.
+///
+/// Each field is `None` if its sysctl does not exist, for example because
+/// nfsd.ko isn't loaded.
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct Pnfs {
+    /// Number of data servers that each file is mirrored across
+    pub mirror_level: Option<u64>,
+}
+
+impl Pnfs {
+    #[cfg(target_os = "freebsd")]
+    fn collect() -> Result<Self> {
+        Ok(Pnfs {
+            mirror_level: sysctl_int(c"vfs.nfsd.pnfsmirror")?,
+        })
+    }
+}
+
+/// Hit and miss counts for one of the client's caches
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct PerHitMiss {
+    pub hits:   u64,
+    pub misses: u64,
+}
+
+impl PerHitMiss {
+    /// Construct from one of the kernel's buffer cache counter pairs.
+    ///
+    /// The kernel counts every buffer cache operation, and separately counts
+    /// the ones that had to go to the server.
+    fn from_bio(total: u64, bios: u64) -> Self {
+        PerHitMiss {
+            hits:   total.saturating_sub(bios),
+            misses: bios,
+        }
+    }
+
+    /// Fraction of cache lookups since `prev` that were hits.
+    pub fn hit_ratio(&self, prev: &PerHitMiss) -> f64 {
+        ratio(
+            self.hits,
+            prev.hits,
+            self.hits.wrapping_add(self.misses),
+            prev.hits.wrapping_add(prev.misses),
+        )
+    }
+}
+
+/// NFS client cache statistics
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct ClientCache {
+    /// Access cache
+    pub access:       PerHitMiss,
+    /// Attribute cache
+    pub attr:         PerHitMiss,
+    /// Buffer cache, for reads
+    pub bio_read:     PerHitMiss,
+    /// Buffer cache, for readdir
+    pub bio_readdir:  PerHitMiss,
+    /// Buffer cache, for readlink
+    pub bio_readlink: PerHitMiss,
+    /// Buffer cache, for writes
+    pub bio_write:    PerHitMiss,
+    /// Directory EOF cache
+    pub direof:       PerHitMiss,
+    /// Name lookup cache
+    pub lookup:       PerHitMiss,
+}
+
+impl ClientCache {
+    /// Every cache, along with a name suitable for use as a label
+    pub fn by_name(&self) -> [(&'static str, &PerHitMiss); 8] {
+        [
+            ("access", &self.access),
+            ("attr", &self.attr),
+            ("bio_read", &self.bio_read),
+            ("bio_readdir", &self.bio_readdir),
+            ("bio_readlink", &self.bio_readlink),
+            ("bio_write", &self.bio_write),
+            ("direof", &self.direof),
+            ("lookup", &self.lookup),
+        ]
+    }
+}
+
+/// Counts of one scope of NFS v4 state held by the client
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct PerClientState {
     pub lock_owner: u64,
     pub locks:      u64,
     pub open_owner: u64,
     pub opens:      u64,
 }
 
+/// NFS v4 state held by the client
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct ClientState {
+    /// Number of delegations held by the client
+    pub delegs:  u64,
+    /// Number of pNFS layouts held by the client
+    pub layouts: u64,
+    /// State handled locally by the client, while it holds a delegation
+    pub local:   PerClientState,
+    /// State acquired from the server
+    pub remote:  PerClientState,
+}
+
+/// NFS client statistics
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct ClientStat {
+    pub cache:      ClientCache,
+    /// Total number of replies that failed to parse
+    pub invalid:    u64,
+    /// Total number of reads and writes that bypassed the buffer cache
+    pub physios:    PerRW,
+    /// Total number of RPCs sent by the client
+    pub requests:   u64,
+    /// Total number of RPCs retried by the client
+    pub retries:    u64,
+    /// Count of each RPC sent by the client
+    pub rpcs:       PerClientRPC,
+    pub state:      ClientState,
+    /// Total number of RPCs that timed out
+    pub timeouts:   u64,
+    /// Total number of replies that matched no outstanding request
+    pub unexpected: u64,
+}
+
+impl ClientStat {
+    /// Fraction of RPCs sent since `prev` that were retried.
+    pub fn retry_ratio(&self, prev: &ClientStat) -> f64 {
+        ratio(self.retries, prev.retries, self.requests, prev.requests)
+    }
+
+    /// Fraction of RPCs sent since `prev` that timed out.
+    pub fn timeout_ratio(&self, prev: &ClientStat) -> f64 {
+        ratio(self.timeouts, prev.timeouts, self.requests, prev.requests)
+    }
+}
+
+/// Statistics of one operation on one NFS mount
+#[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct MountOp {
+    /// Number of times the operation was requested
+    pub ops:            u64,
+    /// Number of times it was transmitted, including retransmissions
+    pub transmissions:  u64,
+    pub major_timeouts: u64,
+    pub bytes_sent:     u64,
+    pub bytes_received: u64,
+    /// Cumulative time spent waiting to be transmitted, in milliseconds
+    pub queue_ms:       u64,
+    /// Cumulative time waiting for the server's reply, in milliseconds
+    pub rtt_ms:         u64,
+    /// Cumulative time from request to completion, in milliseconds
+    pub execute_ms:     u64,
+    /// Number of operations that failed.  Older kernels don't count them.
+    pub errors:         Option<u64>,
+}
+
+/// Bytes transferred through one NFS mount
 #[derive(Clone, Copy, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct MountBytes {
+    /// Read and written by applications through the page cache
+    pub normal: PerRW,
+    /// Read and written by applications with O_DIRECT
+    pub direct: PerRW,
+    /// Read from and written to the server
+    pub server: PerRW,
+}
+
+/// Statistics of one NFS mount.  Only Linux reports these.
+#[derive(Clone, Debug, Default, Decode, Eq, PartialEq, Encode)]
+pub struct MountStat {
+    pub mountpoint: String,
+    /// Host name or address of the NFS server
+    pub server:     String,
+    /// Path exported by the server
+    pub export:     String,
+    /// NFS protocol version, like "3" or "4.2"
+    pub version:    String,
+    pub bytes:      MountBytes,
+    /// Statistics of each operation, by the name that the OS uses
+    pub ops:        Vec<(String, MountOp)>,
+}
+
+/// Ratio of the increase in one counter to the increase in another.
+///
+/// Returns 0 if the denominator didn't increase, or if either counter went
+/// backwards.
+fn ratio(num: u64, prev_num: u64, denom: u64, prev_denom: u64) -> f64 {
+    match (num.checked_sub(prev_num), denom.checked_sub(prev_denom)) {
+        (Some(n), Some(d)) if d > 0 => n as f64 / d as f64,
+        _ => 0.0,
+    }
+}
+
+/// Statistics of the NFS client and server.  Fields that are `None` aren't
+/// reported by the OS that the statistics came from.
+#[derive(Clone, Debug, Default, Decode, Eq, PartialEq, Encode)]
 pub struct NfsStat {
-    /// Total time in ns that nfsd was busy with at least one operation.
-    /// May wrap!
-    pub busytime:     u64,
+    /// Total time that nfsd was busy with at least one operation
+    pub busytime:       Option<Bintime>,
+    /// Nanoseconds since nfsd last completed an operation, or since it became
+    /// busy if it hasn't completed one since.  0 if nfsd is idle.  `None` if
+    /// the time of the sample is unknown.
+    pub since_progress: Option<u64>,
     /// Total bytes processed by each operation
-    pub bytes:        PerRW,
+    pub bytes:          PerRW,
+    /// Count of each NFS v4 callback RPC received by the client
+    pub callback_rpcs:  Option<CallbackRPCs>,
+    /// Statistics of the NFS client
+    pub client:         Option<ClientStat>,
     /// Total number of operations that have completed since boot
-    pub donecnt:      u64,
-    /// Cumulative duration spent processing each operation, in nanoseconds.
-    /// May wrap!
-    pub duration:     PerRWC,
-    pub server_cache: ServerCache,
-    pub server_misc:  ServerMisc,
+    pub donecnt:        Option<u64>,
+    /// Which revision of the kernel's stats structure was read
+    pub kernel_struct:  Option<KernelStruct>,
+    /// Statistics of each NFS mount
+    pub mounts:         Vec<MountStat>,
+    pub net:            Option<ServerNet>,
+    /// nfsd isn't running, so there are no server statistics at all
+    pub nfsd_absent:    bool,
+    /// Total bytes processed by every operation
+    pub op_bytes:       Option<PerRPC>,
+    /// Count of every operation that has completed
+    pub op_done:        Option<PerRPC>,
+    /// Cumulative duration spent processing every operation
+    pub op_duration:    Option<PerRPC<Bintime>>,
+    pub pnfs:           Pnfs,
+    pub rpc:            Option<ServerRpc>,
+    pub server_cache:   ServerCache,
+    pub server_misc:    Option<ServerMisc>,
     /// Count of each RPC processed by the server
-    pub server_rpcs:  PerRPC,
+    pub server_rpcs:    PerRPC,
     /// Total number of operations that have been started since boot
-    pub startcnt:     u64,
+    pub startcnt:       Option<u64>,
+    /// Number of nfsd threads.  On FreeBSD, from the `vfs.nfsd.threads`
+    /// sysctl, which doesn't exist until nfsd.ko is loaded.
+    pub threads:        Option<u64>,
+}
+
+impl NfsStat {
+    /// Number of operations that nfsd has started but not yet completed
+    pub fn in_flight(&self) -> Option<u64> {
+        Some(self.startcnt?.saturating_sub(self.donecnt?))
+    }
+
+    /// Have nfsd's counters been reset since `prev` was collected?  That
+    /// happens when nfsd restarts or its module is reloaded.
+    pub fn is_reset_since(&self, prev: &NfsStat) -> bool {
+        fn went_back(cur: &PerRPC, prev: &PerRPC) -> bool {
+            cur.0.iter().zip(prev.0.iter()).any(|(c, p)| c < p)
+        }
+        fn opt_went_back(cur: &Option<PerRPC>, prev: &Option<PerRPC>) -> bool {
+            matches!((cur, prev), (Some(c), Some(p)) if went_back(c, p))
+        }
+        let startcnt_went_back = matches!(
+            (self.startcnt, prev.startcnt),
+            (Some(c), Some(p)) if c < p
+        );
+        let calls_went_back = matches!(
+            (self.rpc, prev.rpc),
+            (Some(c), Some(p)) if c.calls < p.calls
+        );
+        startcnt_went_back
+            || calls_went_back
+            || went_back(&self.server_rpcs, &prev.server_rpcs)
+            || opt_went_back(&self.op_done, &prev.op_done)
+            || opt_went_back(&self.op_bytes, &prev.op_bytes)
+    }
+}
+
+/// A raw, undecoded sample of the kernel's statistics
+#[derive(Clone, Debug, Decode, Eq, PartialEq, Encode)]
+pub struct Snapshot {
+    /// Raw image of the kernel's `struct nfsstatsv1`, in any supported layout
+    pub image:     Vec<u8>,
+    /// The kernel's `kern.osreldate`, if known
+    pub osreldate: Option<u64>,
+    /// Time since boot when the image was taken, in nanoseconds
+    pub uptime_ns: u64,
 }
 
+/// If the NFS modules aren't loaded, nfssvc would raise SIGSYS and kill the
+/// process.  Ignore it, so nfssvc fails with ENOSYS instead.  This affects the
+/// whole process, so it must be called once at startup, before
+/// [`snapshot`].
+#[cfg(target_os = "freebsd")]
+pub fn ignore_sigsys() {
+    unsafe { libc::signal(libc::SIGSYS, libc::SIG_IGN) };
+}
+
+/// Take a raw sample of the running kernel's statistics
+#[cfg(target_os = "freebsd")]
+pub fn snapshot() -> Result<Snapshot> {
+    let osreldate = sysctl_int(c"kern.osreldate")?;
+    let layout = layouts::Layout::for_osreldate(osreldate);
+    let image = layouts::fetch_image(layout)?;
+    Ok(Snapshot {
+        image,
+        osreldate,
+        uptime_ns: uptime_ns()?,
+    })
+}
+
+/// Collect statistics from the running kernel
+#[cfg(target_os = "freebsd")]
 pub fn collect() -> Result<NfsStat> {
-    let mut raw = ffi::nfsstatsv1 {
-        vers: ffi::NFSSTATS_V1 as i32,
-        ..Default::default()
+    let snapshot = snapshot()?;
+    let (raw, layout) = layouts::from_image(&snapshot.image)?;
+    let kernel_struct = KernelStruct {
+        layout,
+        osreldate: snapshot.osreldate,
     };
-    let flag = ffi::NFSSVC_GETSTATS | ffi::NFSSVC_NEWSTRUCT;
-    let raw = unsafe {
-        let r = ffi::nfssvc(flag as i32, &mut raw as *mut _ as *mut c_void);
-        if r != 0 {
-            return Err(Error::last_os_error());
-        }
-        raw
+    let mut nfs_stat = from_raw(
+        &raw,
+        kernel_struct,
+        Pnfs::collect()?,
+        Some(snapshot.uptime_ns),
+    );
+    nfs_stat.threads = sysctl_int(c"vfs.nfsd.threads")?;
+    Ok(nfs_stat)
+}
+
+/// Decode a raw image of the kernel's `struct nfsstatsv1`, in any supported
+/// layout.  The image doesn't say when it was taken, so how long nfsd has been
+/// busy is unknown.
+pub fn from_image(image: &[u8]) -> Result<NfsStat> {
+    let (raw, layout) = layouts::from_image(image)?;
+    let kernel_struct = KernelStruct {
+        layout,
+        osreldate: None,
+    };
+    Ok(from_raw(&raw, kernel_struct, Pnfs::default(), None))
+}
+
+/// Decode a previously recorded sample.  pNFS settings aren't part of the
+/// sample, so they're left at their defaults.
+pub fn from_snapshot(snapshot: &Snapshot) -> Result<NfsStat> {
+    let (raw, layout) = layouts::from_image(&snapshot.image)?;
+    let kernel_struct = KernelStruct {
+        layout,
+        osreldate: snapshot.osreldate,
     };
+    Ok(from_raw(
+        &raw,
+        kernel_struct,
+        Pnfs::default(),
+        Some(snapshot.uptime_ns),
+    ))
+}
+
+/// Convert the kernel's statistics.  `uptime_ns` is the current time on the
+/// kernel's `binuptime` clock, if known.
+fn from_raw(
+    raw: &ffi::nfsstatsv1,
+    kernel_struct: KernelStruct,
+    pnfs: Pnfs,
+    uptime_ns: Option<u64>,
+) -> NfsStat {
     let bytes = PerRW {
         read:  raw.srvbytes[ffi::NFSV4OP_READ as usize],
         write: raw.srvbytes[ffi::NFSV4OP_WRITE as usize],
     };
-    let duration = PerRWC {
-        read:   bintime_to_ns(&raw.srvduration[ffi::NFSV4OP_READ as usize]),
-        write:  bintime_to_ns(&raw.srvduration[ffi::NFSV4OP_WRITE as usize]),
-        commit: bintime_to_ns(&raw.srvduration[ffi::NFSV4OP_COMMIT as usize]),
-    };
     let server_cache = ServerCache {
-        inprog:   raw.srvcache_inproghits,
-        nonidem:  raw.srvcache_nonidemdonehits,
+        hits:     None,
+        inprog:   Some(raw.srvcache_inproghits),
+        nonidem:  Some(raw.srvcache_nonidemdonehits),
         misses:   raw.srvcache_misses,
-        size:     i64::max(0, i64::from(raw.srvcache_size)) as u64,
-        tcp_peak: raw.srvcache_tcppeak,
+        nocache:  None,
+        size:     Some(i64::max(0, i64::from(raw.srvcache_size)) as u64),
+        tcp_peak: Some(raw.srvcache_tcppeak),
     };
-    let server_misc = ServerMisc {
+    let server_misc = Some(ServerMisc {
         clients:    raw.srvclients,
         delegs:     raw.srvdelegates,
+        layouts:    raw.srvlayouts,
         lock_owner: raw.srvlockowners,
         locks:      raw.srvlocks,
         open_owner: raw.srvopenowners,
         opens:      raw.srvopens,
-    };
-    let server_rpcs = PerRPC {
-        access:          raw.srvrpccnt[ffi::NFSV4OP_ACCESS as usize],
-        backchannelctrl: raw.srvrpccnt[ffi::NFSV4OP_BACKCHANNELCTL as usize],
-        bindconntosess:  raw.srvrpccnt[ffi::NFSV4OP_BINDCONNTOSESS as usize],
-        close:           raw.srvrpccnt[ffi::NFSV4OP_CLOSE as usize],
-        commit:          raw.srvrpccnt[ffi::NFSV4OP_COMMIT as usize],
-        create:          raw.srvrpccnt[ffi::NFSV4OP_CREATE as usize],
-        createsess:      raw.srvrpccnt[ffi::NFSV4OP_CREATESESSION as usize],
-        delegpurge:      raw.srvrpccnt[ffi::NFSV4OP_DELEGPURGE as usize],
-        delegreturn:     raw.srvrpccnt[ffi::NFSV4OP_DELEGRETURN as usize],
-        destroyclid:     raw.srvrpccnt[ffi::NFSV4OP_DESTROYCLIENTID as usize],
-        destroysess:     raw.srvrpccnt[ffi::NFSV4OP_DESTROYSESSION as usize],
-        exchangeid:      raw.srvrpccnt[ffi::NFSV4OP_EXCHANGEID as usize],
-        freestateid:     raw.srvrpccnt[ffi::NFSV4OP_FREESTATEID as usize],
-        fsinfo:          raw.srvrpccnt[ffi::NFSV4OP_FSINFO as usize],
-        fsstat:          raw.srvrpccnt[ffi::NFSV4OP_FSSTAT as usize],
-        getattr:         raw.srvrpccnt[ffi::NFSV4OP_GETATTR as usize],
-        getdevinfo:      raw.srvrpccnt[ffi::NFSV4OP_GETDEVINFO as usize],
-        getdevlist:      raw.srvrpccnt[ffi::NFSV4OP_GETDEVLIST as usize],
-        getdirdeleg:     raw.srvrpccnt[ffi::NFSV4OP_GETDIRDELEG as usize],
-        getfh:           raw.srvrpccnt[ffi::NFSV4OP_GETFH as usize],
-        layoutcommit:    raw.srvrpccnt[ffi::NFSV4OP_LAYOUTCOMMIT as usize],
-        layoutget:       raw.srvrpccnt[ffi::NFSV4OP_LAYOUTGET as usize],
-        layoutreturn:    raw.srvrpccnt[ffi::NFSV4OP_LAYOUTRETURN as usize],
-        link:            raw.srvrpccnt[ffi::NFSV4OP_LINK as usize],
-        lock:            raw.srvrpccnt[ffi::NFSV4OP_LOCK as usize],
-        lockt:           raw.srvrpccnt[ffi::NFSV4OP_LOCKT as usize],
-        locku:           raw.srvrpccnt[ffi::NFSV4OP_LOCKU as usize],
-        lookup:          raw.srvrpccnt[ffi::NFSV4OP_LOOKUP as usize],
-        lookupp:         raw.srvrpccnt[ffi::NFSV4OP_LOOKUPP as usize],
-        mkdir:           raw.srvrpccnt[ffi::NFSV4OP_MKDIR as usize],
-        mknod:           raw.srvrpccnt[ffi::NFSV4OP_MKNOD as usize],
-        nverify:         raw.srvrpccnt[ffi::NFSV4OP_NVERIFY as usize],
-        open:            raw.srvrpccnt[ffi::NFSV4OP_OPEN as usize],
-        openattr:        raw.srvrpccnt[ffi::NFSV4OP_OPENATTR as usize],
-        openconfirm:     raw.srvrpccnt[ffi::NFSV4OP_OPENCONFIRM as usize],
-        opendgrd:        raw.srvrpccnt[ffi::NFSV4OP_OPENDOWNGRADE as usize],
-        pathconf:        raw.srvrpccnt[ffi::NFSV4OP_PATHCONF as usize],
-        putfh:           raw.srvrpccnt[ffi::NFSV4OP_PUTFH as usize],
-        putpubfh:        raw.srvrpccnt[ffi::NFSV4OP_PUTPUBFH as usize],
-        putrootfh:       raw.srvrpccnt[ffi::NFSV4OP_PUTROOTFH as usize],
-        read:            raw.srvrpccnt[ffi::NFSV4OP_READ as usize],
-        readdir:         raw.srvrpccnt[ffi::NFSV4OP_READDIR as usize],
-        readdirplus:     raw.srvrpccnt[ffi::NFSV4OP_READDIRPLUS as usize],
-        readlink:        raw.srvrpccnt[ffi::NFSV4OP_READLINK as usize],
-        reclaimcompl:    raw.srvrpccnt[ffi::NFSV4OP_RECLAIMCOMPL as usize],
-        rellckown:       raw.srvrpccnt[ffi::NFSV4OP_RELEASELCKOWN as usize],
-        remove:          raw.srvrpccnt[ffi::NFSV4OP_REMOVE as usize],
-        rename:          raw.srvrpccnt[ffi::NFSV4OP_RENAME as usize],
-        renew:           raw.srvrpccnt[ffi::NFSV4OP_RENEW as usize],
-        restorefh:       raw.srvrpccnt[ffi::NFSV4OP_RESTOREFH as usize],
-        rmdir:           raw.srvrpccnt[ffi::NFSV4OP_RMDIR as usize],
-        savefh:          raw.srvrpccnt[ffi::NFSV4OP_SAVEFH as usize],
-        secinfo:         raw.srvrpccnt[ffi::NFSV4OP_SECINFO as usize],
-        secinfononame:   raw.srvrpccnt[ffi::NFSV4OP_SECINFONONAME as usize],
-        sequence:        raw.srvrpccnt[ffi::NFSV4OP_SEQUENCE as usize],
-        setattr:         raw.srvrpccnt[ffi::NFSV4OP_SETATTR as usize],
-        setclid:         raw.srvrpccnt[ffi::NFSV4OP_SETCLIENTID as usize],
-        setclidcf:       raw.srvrpccnt[ffi::NFSV4OP_SETCLIENTIDCFRM as usize],
-        setssv:          raw.srvrpccnt[ffi::NFSV4OP_SETSSV as usize],
-        symlink:         raw.srvrpccnt[ffi::NFSV4OP_SYMLINK as usize],
-        teststateid:     raw.srvrpccnt[ffi::NFSV4OP_TESTSTATEID as usize],
-        v3create:        raw.srvrpccnt[ffi::NFSV4OP_V3CREATE as usize],
-        verify:          raw.srvrpccnt[ffi::NFSV4OP_VERIFY as usize],
-        wantdeleg:       raw.srvrpccnt[ffi::NFSV4OP_WANTDELEG as usize],
-        write:           raw.srvrpccnt[ffi::NFSV4OP_WRITE as usize],
-    };
-    Ok(NfsStat {
+    });
+    let op_bytes = Some(PerRPC::from_fn(|i| raw.srvbytes[i]));
+    let op_done = Some(PerRPC::from_fn(|i| raw.srvops[i]));
+    let op_duration =
+        Some(PerRPC::from_fn(|i| Bintime::from(&raw.srvduration[i])));
+    let server_rpcs = PerRPC::from_fn(|i| raw.srvrpccnt[i]);
+    let mut callback_rpcs = CallbackRPCs::default();
+    for (count, op) in callback_rpcs.0.iter_mut().zip(CALLBACK_OPS) {
+        *count = raw.cbrpccnt[op.idx as usize];
+    }
+    let client = Some(ClientStat {
+        cache:      ClientCache {
+            access:       PerHitMiss {
+                hits:   raw.accesscache_hits,
+                misses: raw.accesscache_misses,
+            },
+            attr:         PerHitMiss {
+                hits:   raw.attrcache_hits,
+                misses: raw.attrcache_misses,
+            },
+            bio_read:     PerHitMiss::from_bio(
+                raw.biocache_reads,
+                raw.read_bios,
+            ),
+            bio_readdir:  PerHitMiss::from_bio(
+                raw.biocache_readdirs,
+                raw.readdir_bios,
+            ),
+            bio_readlink: PerHitMiss::from_bio(
+                raw.biocache_readlinks,
+                raw.readlink_bios,
+            ),
+            bio_write:    PerHitMiss::from_bio(
+                raw.biocache_writes,
+                raw.write_bios,
+            ),
+            direof:       PerHitMiss {
+                hits:   raw.direofcache_hits,
+                misses: raw.direofcache_misses,
+            },
+            lookup:       PerHitMiss {
+                hits:   raw.lookupcache_hits,
+                misses: raw.lookupcache_misses,
+            },
+        },
+        invalid:    raw.rpcinvalid,
+        physios:    PerRW {
+            read:  raw.read_physios,
+            write: raw.write_physios,
+        },
+        requests:   raw.rpcrequests,
+        retries:    raw.rpcretries,
+        state:      ClientState {
+            delegs:  raw.cldelegates,
+            layouts: raw.cllayouts,
+            local:   PerClientState {
+                lock_owner: raw.cllocallockowners,
+                locks:      raw.cllocallocks,
+                open_owner: raw.cllocalopenowners,
+                opens:      raw.cllocalopens,
+            },
+            remote:  PerClientState {
+                lock_owner: raw.cllockowners,
+                locks:      raw.cllocks,
+                open_owner: raw.clopenowners,
+                opens:      raw.clopens,
+            },
+        },
+        rpcs:       PerClientRPC(
+            raw.rpccnt[..CLIENT_NRPCS].try_into().unwrap(),
+        ),
+        timeouts:   raw.rpctimeouts,
+        unexpected: raw.rpcunexpected,
+    });
+    let since_progress = uptime_ns.map(|uptime_ns| {
+        if raw.srvstartcnt > raw.srvdonecnt {
+            uptime_ns.saturating_sub(bintime_to_ns(&raw.busyfrom))
+        } else {
+            0
+        }
+    });
+    NfsStat {
         bytes,
-        duration,
-        startcnt: raw.srvstartcnt,
-        donecnt: raw.srvdonecnt,
-        busytime: bintime_to_ns(&raw.busytime),
+        callback_rpcs: Some(callback_rpcs),
+        client,
+        startcnt: Some(raw.srvstartcnt),
+        donecnt: Some(raw.srvdonecnt),
+        kernel_struct: Some(kernel_struct),
+        mounts: Vec::new(),
+        net: None,
+        nfsd_absent: false,
+        op_bytes,
+        op_done,
+        op_duration,
+        pnfs,
+        rpc: None,
+        busytime: Some(Bintime::from(&raw.busytime)),
+        since_progress,
         server_cache,
         server_misc,
         server_rpcs,
-    })
+        threads: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod nfs_stat {
+        use super::*;
+
+        #[test]
+        fn in_flight() {
+            let ns = NfsStat {
+                startcnt: Some(1000),
+                donecnt: Some(995),
+                ..Default::default()
+            };
+            assert_eq!(ns.in_flight(), Some(5));
+        }
+
+        /// The kernel doesn't update startcnt and donecnt atomically, so
+        /// donecnt may briefly appear to exceed startcnt.
+        #[test]
+        fn in_flight_race() {
+            let ns = NfsStat {
+                startcnt: Some(1000),
+                donecnt: Some(1001),
+                ..Default::default()
+            };
+            assert_eq!(ns.in_flight(), Some(0));
+        }
+
+        #[test]
+        fn is_reset_since_no() {
+            let prev = NfsStat {
+                startcnt: Some(1000),
+                ..Default::default()
+            };
+            let mut cur = prev.clone();
+            cur.startcnt = Some(1001);
+            cur.op_done = Some(PerRPC::from_fn(|_| 1));
+            assert!(!cur.is_reset_since(&prev));
+            assert!(!prev.is_reset_since(&prev));
+        }
+
+        #[test]
+        fn is_reset_since_startcnt() {
+            let prev = NfsStat {
+                startcnt: Some(1000),
+                ..Default::default()
+            };
+            let cur = NfsStat {
+                startcnt: Some(3),
+                ..Default::default()
+            };
+            assert!(cur.is_reset_since(&prev));
+        }
+
+        /// Any single op counter going backwards indicates a reset
+        #[test]
+        fn is_reset_since_op() {
+            let mut prev = NfsStat {
+                startcnt: Some(1000),
+                ..Default::default()
+            };
+            prev.server_rpcs.0[5] = 10;
+            let mut cur = prev.clone();
+            cur.startcnt = Some(1001);
+            cur.server_rpcs.0[5] = 9;
+            assert!(cur.is_reset_since(&prev));
+        }
+
+        /// Without FreeBSD's startcnt, Linux's RPC count detects resets
+        #[test]
+        fn is_reset_since_rpc_calls() {
+            let prev = NfsStat {
+                rpc: Some(ServerRpc {
+                    calls: 1000,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let mut cur = prev.clone();
+            assert!(!cur.is_reset_since(&prev));
+            cur.rpc = Some(ServerRpc::default());
+            assert!(cur.is_reset_since(&prev));
+        }
+    }
+
+    mod per_hit_miss {
+        use super::*;
+
+        #[test]
+        fn from_bio() {
+            let phm = PerHitMiss::from_bio(100, 30);
+            assert_eq!(phm.hits, 70);
+            assert_eq!(phm.misses, 30);
+        }
+
+        #[test]
+        fn hit_ratio() {
+            let prev = PerHitMiss {
+                hits:   100,
+                misses: 100,
+            };
+            let cur = PerHitMiss {
+                hits:   190,
+                misses: 110,
+            };
+            assert_eq!(cur.hit_ratio(&prev), 0.9);
+        }
+
+        #[test]
+        fn hit_ratio_idle() {
+            let prev = PerHitMiss {
+                hits:   100,
+                misses: 100,
+            };
+            assert_eq!(prev.hit_ratio(&prev), 0.0);
+        }
+    }
+
+    mod server_ops {
+        use std::collections::HashSet;
+
+        use super::*;
+
+        fn is_mapped(idx: u32) -> bool {
+            SERVER_OPS.iter().any(|op| op.idx == idx)
+        }
+
+        /// Every op defined by NFS v4.0 must be mapped
+        #[test]
+        fn v4_0() {
+            for idx in ffi::NFSV4OP_ACCESS..ffi::NFSV4OP_NOPS {
+                assert!(is_mapped(idx), "NFSV4OP {idx} is unmapped");
+            }
+        }
+
+        /// Every op counted by the kernel, including the fake ones used for
+        /// NFS v3, must be mapped
+        #[test]
+        fn all() {
+            let nops = ffi::NFSV42_NOPS + ffi::NFSV4OP_FAKENOPS;
+            for idx in ffi::NFSV4OP_ACCESS..nops {
+                assert!(is_mapped(idx), "NFSV4OP {idx} is unmapped");
+            }
+            assert_eq!(SERVER_NOPS, (nops - ffi::NFSV4OP_ACCESS) as usize);
+        }
+
+        #[test]
+        fn fits_in_srvrpccnt() {
+            let raw = ffi::nfsstatsv1::default();
+            for op in SERVER_OPS {
+                assert!((op.idx as usize) < raw.srvrpccnt.len());
+            }
+        }
+
+        #[test]
+        fn unique_indices() {
+            let indices =
+                SERVER_OPS.iter().map(|op| op.idx).collect::<HashSet<_>>();
+            assert_eq!(indices.len(), SERVER_NOPS);
+        }
+
+        #[test]
+        fn unique_names() {
+            let names =
+                SERVER_OPS.iter().map(|op| op.name).collect::<HashSet<_>>();
+            assert_eq!(names.len(), SERVER_NOPS);
+        }
+    }
+
+    mod client_stat {
+        use super::*;
+
+        fn stat(requests: u64, retries: u64, timeouts: u64) -> ClientStat {
+            ClientStat {
+                requests,
+                retries,
+                timeouts,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn retry_ratio() {
+            let prev = stat(1000, 10, 0);
+            let cur = stat(1200, 60, 0);
+            assert_eq!(cur.retry_ratio(&prev), 0.25);
+        }
+
+        #[test]
+        fn timeout_ratio() {
+            let prev = stat(1000, 0, 3);
+            let cur = stat(1100, 0, 13);
+            assert_eq!(cur.timeout_ratio(&prev), 0.1);
+        }
+
+        /// With no new requests, the ratio should be 0, not NaN
+        #[test]
+        fn idle() {
+            let prev = stat(1000, 10, 1);
+            assert_eq!(prev.retry_ratio(&prev), 0.0);
+            assert_eq!(prev.timeout_ratio(&prev), 0.0);
+        }
+
+        /// If the counters went backwards, the ratio is meaningless
+        #[test]
+        fn reset() {
+            let prev = stat(1000, 10, 1);
+            let cur = stat(100, 5, 0);
+            assert_eq!(cur.retry_ratio(&prev), 0.0);
+            assert_eq!(cur.timeout_ratio(&prev), 0.0);
+        }
+    }
+
+    mod callback_ops {
+        use std::collections::HashSet;
+
+        use super::*;
+
+        /// Every callback op known to the kernel must be mapped
+        #[test]
+        fn all() {
+            for idx in ffi::NFSV4OP_CBGETATTR..=ffi::NFSV4OP_CBOFFLOAD {
+                assert!(
+                    CALLBACK_OPS.iter().any(|op| op.idx == idx),
+                    "NFSV4OP_CB {idx} is unmapped"
+                );
+            }
+        }
+
+        #[test]
+        fn fits_in_cbrpccnt() {
+            let raw = ffi::nfsstatsv1::default();
+            for op in CALLBACK_OPS {
+                assert!((op.idx as usize) < raw.cbrpccnt.len());
+            }
+        }
+
+        #[test]
+        fn unique_names() {
+            let names = CALLBACK_OPS
+                .iter()
+                .map(|op| op.name)
+                .collect::<HashSet<_>>();
+            assert_eq!(names.len(), CALLBACK_NOPS);
+        }
+    }
+
+    mod client_rpc_names {
+        use std::collections::HashSet;
+
+        use super::*;
+
+        /// The kernel's array must be large enough for every op we know of
+        #[test]
+        fn fits_in_rpccnt() {
+            let raw = ffi::nfsstatsv1::default();
+            assert!(CLIENT_NRPCS <= raw.rpccnt.len());
+        }
+
+        /// Every index must be mapped, in order, exactly once
+        #[test]
+        fn indices() {
+            for (i, (idx, _)) in CLIENT_RPC_NAMES.iter().enumerate() {
+                assert_eq!(i, *idx as usize);
+            }
+        }
+
+        #[test]
+        fn unique_names() {
+            let names = CLIENT_RPC_NAMES
+                .iter()
+                .map(|(_, name)| name)
+                .collect::<HashSet<_>>();
+            assert_eq!(names.len(), CLIENT_RPC_NAMES.len());
+        }
+
+        /// Spot check a few ops whose numbering differs from the server's
+        #[test]
+        fn spot_check() {
+            assert_eq!(CLIENT_RPC_NAMES[1], (1, "GetAttr"));
+            assert_eq!(CLIENT_RPC_NAMES[3], (3, "Lookup"));
+            assert_eq!(CLIENT_RPC_NAMES[27], (27, "Open"));
+            assert_eq!(CLIENT_RPC_NAMES[53], (53, "CommitDS"));
+        }
+    }
 }
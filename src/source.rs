@@ -0,0 +1,311 @@
+//! Sources of NFS statistics
+use std::{fmt, fs, io, path::PathBuf, time::Instant};
+
+#[cfg(target_os = "linux")]
+use crate::linux;
+use crate::{
+    nfs::{self, NfsStat},
+    trace,
+};
+
+/// Why NFS stats could not be collected
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed, like `nfssvc(NFSSVC_GETSTATS)`, or reading a
+    /// fixture, or talking to the Casper helper.
+    Os(io::Error),
+    /// The Casper helper returned something unexpected
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    BadReply(&'static str),
+}
+
+impl Error {
+    /// The error's errno, in decimal, for use as a metric label
+    pub fn errno(&self) -> String {
+        match self {
+            Error::Os(e) => e.raw_os_error(),
+            Error::BadReply(_) => None,
+        }
+        .map(|errno| errno.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Os(e) => write!(f, "{e}"),
+            Error::BadReply(msg) => {
+                write!(f, "bad reply from the Casper helper: {msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Os(e)
+    }
+}
+
+/// Something that can provide NFS statistics
+pub trait StatsSource: Send {
+    /// Collect the current statistics
+    fn nfsstat(&mut self) -> Result<NfsStat, Error>;
+}
+
+/// Collect statistics directly from the kernel.  This won't work in capability
+/// mode.
+#[cfg(target_os = "freebsd")]
+#[derive(Debug, Default)]
+pub struct Kernel {}
+
+#[cfg(target_os = "freebsd")]
+impl StatsSource for Kernel {
+    fn nfsstat(&mut self) -> Result<NfsStat, Error> {
+        Ok(nfs::collect()?)
+    }
+}
+
+/// Read statistics from Linux's procfs.  Client statistics are per-mount.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Procfs {
+    /// Where to read server statistics, if they're wanted
+    nfsd:          Option<PathBuf>,
+    /// Where to read client statistics, if they're wanted
+    mountstats:    Option<PathBuf>,
+    /// Is it an error for server statistics to be missing?
+    nfsd_required: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Procfs {
+    /// Only the requested statistics are read.  In particular, server
+    /// statistics don't exist unless nfsd is loaded.  Unless they're
+    /// `nfsd_required`, they're then left out.
+    pub fn new(client: bool, server: bool, nfsd_required: bool) -> Self {
+        Self::with_paths(
+            server.then(|| PathBuf::from(linux::NFSD_PATH)),
+            client.then(|| PathBuf::from(linux::MOUNTSTATS_PATH)),
+            nfsd_required,
+        )
+    }
+
+    /// Like [`Procfs::new`], but read from the given files instead
+    pub fn with_paths(
+        nfsd: Option<PathBuf>,
+        mountstats: Option<PathBuf>,
+        nfsd_required: bool,
+    ) -> Self {
+        Procfs {
+            nfsd,
+            mountstats,
+            nfsd_required,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl StatsSource for Procfs {
+    fn nfsstat(&mut self) -> Result<NfsStat, Error> {
+        let mut nfs_stat = match &self.nfsd {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => linux::parse_nfsd(&text)?,
+                Err(e)
+                    if e.kind() == io::ErrorKind::NotFound
+                        && !self.nfsd_required =>
+                {
+                    NfsStat {
+                        nfsd_absent: true,
+                        ..Default::default()
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            },
+            None => NfsStat::default(),
+        };
+        if let Some(path) = &self.mountstats {
+            let text = fs::read_to_string(path)?;
+            nfs_stat.mounts = linux::parse_mountstats(&text)?;
+        }
+        Ok(nfs_stat)
+    }
+}
+
+/// Read statistics from a raw image of the kernel's `struct nfsstatsv1`, in
+/// any supported layout.  The file is reread for every sample, so it may be
+/// replaced in between.
+#[derive(Debug)]
+pub struct Fixture {
+    path: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(path: PathBuf) -> Self {
+        Fixture { path }
+    }
+}
+
+impl StatsSource for Fixture {
+    fn nfsstat(&mut self) -> Result<NfsStat, Error> {
+        let image = fs::read(&self.path)?;
+        Ok(nfs::from_image(&image)?)
+    }
+}
+
+/// Replay statistics from a trace recorded by `nfs-exporter record`.  The
+/// trace's first record is served at `start`, and later ones follow at `speed`
+/// times their original pace.  After the end, the last record is repeated.
+#[derive(Debug)]
+pub struct Replay {
+    records: Vec<trace::Record>,
+    speed:   f64,
+    start:   Instant,
+}
+
+impl Replay {
+    /// `records` must not be empty.
+    pub fn new(
+        records: Vec<trace::Record>,
+        speed: f64,
+        start: Instant,
+    ) -> Self {
+        assert!(!records.is_empty(), "cannot replay an empty trace");
+        Replay {
+            records,
+            speed,
+            start,
+        }
+    }
+
+    /// The record to serve at time `now`
+    fn current(&self, now: Instant) -> &trace::Record {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f64();
+        let offset_ns = (elapsed * self.speed * 1e9) as u64;
+        let t = self.records[0].timestamp_ns.saturating_add(offset_ns);
+        let i = self.records.partition_point(|r| r.timestamp_ns <= t);
+        &self.records[i.saturating_sub(1)]
+    }
+}
+
+impl StatsSource for Replay {
+    fn nfsstat(&mut self) -> Result<NfsStat, Error> {
+        let record = self.current(Instant::now());
+        Ok(nfs::from_snapshot(&record.snapshot)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    mod procfs {
+        use super::*;
+
+        fn data(name: &str) -> Option<PathBuf> {
+            let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+            Some(PathBuf::from(dir).join(name))
+        }
+
+        #[test]
+        fn both() {
+            let mut procfs =
+                Procfs::with_paths(data("nfsd"), data("mountstats/v4.2"), true);
+            let nfs_stat = procfs.nfsstat().unwrap();
+            assert!(!nfs_stat.nfsd_absent);
+            assert_eq!(nfs_stat.threads, Some(8));
+            assert!(!nfs_stat.mounts.is_empty());
+        }
+
+        #[test]
+        fn client_only() {
+            let mut procfs =
+                Procfs::with_paths(None, data("mountstats/v4.2"), false);
+            let nfs_stat = procfs.nfsstat().unwrap();
+            assert!(!nfs_stat.mounts.is_empty());
+            assert_eq!(nfs_stat.threads, None);
+        }
+
+        /// A client that doesn't run nfsd still gets its client statistics
+        #[test]
+        fn nfsd_absent() {
+            let dir = tempfile::tempdir().unwrap();
+            let nfsd = Some(dir.path().join("nfsd"));
+            let mut procfs =
+                Procfs::with_paths(nfsd, data("mountstats/v4.2"), false);
+            let nfs_stat = procfs.nfsstat().unwrap();
+            assert!(nfs_stat.nfsd_absent);
+            assert_eq!(nfs_stat.threads, None);
+            assert!(!nfs_stat.mounts.is_empty());
+        }
+
+        /// Unless server statistics were explicitly requested
+        #[test]
+        fn nfsd_required() {
+            let dir = tempfile::tempdir().unwrap();
+            let nfsd = Some(dir.path().join("nfsd"));
+            let mut procfs = Procfs::with_paths(nfsd, None, true);
+            let e = procfs.nfsstat().unwrap_err();
+            assert_eq!(e.errno(), libc::ENOENT.to_string());
+        }
+    }
+
+    mod replay {
+        use std::time::Duration;
+
+        use super::*;
+
+        fn record(timestamp_ns: u64) -> trace::Record {
+            trace::Record {
+                timestamp_ns,
+                snapshot: nfs::Snapshot {
+                    image:     vec![],
+                    osreldate: None,
+                    uptime_ns: 0,
+                },
+            }
+        }
+
+        fn replay(speed: f64, start: Instant) -> Replay {
+            let s = 1_000_000_000;
+            let records =
+                vec![record(100 * s), record(110 * s), record(120 * s)];
+            Replay::new(records, speed, start)
+        }
+
+        #[test]
+        fn real_time() {
+            let start = Instant::now();
+            let r = replay(1.0, start);
+            let at = |secs| r.current(start + Duration::from_secs(secs));
+            assert_eq!(at(0).timestamp_ns, 100_000_000_000);
+            assert_eq!(at(9).timestamp_ns, 100_000_000_000);
+            assert_eq!(at(10).timestamp_ns, 110_000_000_000);
+            assert_eq!(at(19).timestamp_ns, 110_000_000_000);
+            assert_eq!(at(20).timestamp_ns, 120_000_000_000);
+        }
+
+        #[test]
+        fn accelerated() {
+            let start = Instant::now();
+            let r = replay(10.0, start);
+            let at = |secs| r.current(start + Duration::from_secs(secs));
+            assert_eq!(at(0).timestamp_ns, 100_000_000_000);
+            assert_eq!(at(1).timestamp_ns, 110_000_000_000);
+            assert_eq!(at(2).timestamp_ns, 120_000_000_000);
+        }
+
+        /// After the end of the trace, the last record sticks
+        #[test]
+        fn after_end() {
+            let start = Instant::now();
+            let r = replay(1.0, start);
+            let t = start + Duration::from_secs(3600);
+            assert_eq!(r.current(t).timestamp_ns, 120_000_000_000);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Recordings of raw kernel statistics, for replaying them elsewhere.
+//!
+//! A trace is a header followed by any number of records, all bincode-encoded.
+//! Records are only ever appended, so the recorder may be killed at any time.
+//! A partial record at the end of a trace is ignored.
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use bincode_next::{Decode, Encode, config, error::DecodeError};
+
+use crate::nfs::Snapshot;
+
+const BINCODE_CONFIG: config::Configuration<
+    config::LittleEndian,
+    config::Fixint,
+    config::Limit<{ 1 << 20 }>,
+> = config::standard()
+    .with_fixed_int_encoding()
+    .with_limit::<{ 1 << 20 }>();
+
+/// Identifies a file as a trace
+const MAGIC: [u8; 8] = *b"NFSTRACE";
+/// Revision of the trace format
+const VERSION: u32 = 1;
+
+#[derive(Debug, Decode, Encode)]
+struct Header {
+    magic:   [u8; 8],
+    version: u32,
+}
+
+/// One timestamped sample of the kernel's statistics
+#[derive(Clone, Debug, Decode, Eq, PartialEq, Encode)]
+pub struct Record {
+    /// When the sample was taken, in nanoseconds since the epoch
+    pub timestamp_ns: u64,
+    pub snapshot:     Snapshot,
+}
+
+fn invalid(msg: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Decode a whole trace.  Returns its records, and the length of the part of
+/// `bytes` that they occupy.
+fn parse(bytes: &[u8]) -> io::Result<(Vec<Record>, usize)> {
+    let (header, mut pos): (Header, usize) =
+        bincode_next::decode_from_slice(bytes, BINCODE_CONFIG)
+            .map_err(|_| invalid("not an nfs-exporter trace"))?;
+    if header.magic != MAGIC {
+        return Err(invalid("not an nfs-exporter trace"));
+    }
+    if header.version != VERSION {
+        return Err(invalid(format!(
+            "unsupported trace version {}",
+            header.version
+        )));
+    }
+    let mut records = Vec::new();
+    while pos < bytes.len() {
+        match bincode_next::decode_from_slice(&bytes[pos..], BINCODE_CONFIG) {
+            Ok((record, len)) => {
+                records.push(record);
+                pos += len;
+            }
+            Err(DecodeError::UnexpectedEnd { .. }) => {
+                log::warn!("Ignoring a truncated record at the end of a trace");
+                break;
+            }
+            Err(e) => return Err(invalid(e)),
+        }
+    }
+    Ok((records, pos))
+}
+
+/// Read every record of a trace file
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    parse(&fs::read(path)?).map(|(records, _)| records)
+}
+
+/// Appends records to a trace file
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+#[derive(Debug)]
+pub struct Writer {
+    file: fs::File,
+}
+
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+impl Writer {
+    /// Open a trace file for appending, creating it if necessary.  If a
+    /// previous recorder left a partial record at the end, it gets discarded.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            let header = Header {
+                magic:   MAGIC,
+                version: VERSION,
+            };
+            file.write_all(&encode(&header))?;
+        } else {
+            let (_, len) = parse(&fs::read(path)?)?;
+            file.set_len(len as u64)?;
+        }
+        Ok(Writer { file })
+    }
+
+    /// Append one record
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        // Write each record all at once, to minimize the chance of leaving a
+        // partial one.
+        self.file.write_all(&encode(record))
+    }
+}
+
+fn encode<E: Encode>(e: &E) -> Vec<u8> {
+    bincode_next::encode_to_vec(e, BINCODE_CONFIG)
+        .expect("encoding to a Vec cannot fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp_ns: u64) -> Record {
+        Record {
+            timestamp_ns,
+            snapshot: Snapshot {
+                image:     vec![1, 2, 3, 4],
+                osreldate: Some(1_400_000),
+                uptime_ns: timestamp_ns / 2,
+            },
+        }
+    }
+
+    mod writer {
+        use super::*;
+
+        #[test]
+        fn round_trip() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("trace.bin");
+            let mut writer = Writer::append(&path).unwrap();
+            writer.write(&record(10)).unwrap();
+            writer.write(&record(20)).unwrap();
+            drop(writer);
+            assert_eq!(read(&path).unwrap(), vec![record(10), record(20)]);
+        }
+
+        /// Reopening a trace continues after its existing records
+        #[test]
+        fn reopen() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("trace.bin");
+            Writer::append(&path).unwrap().write(&record(10)).unwrap();
+            Writer::append(&path).unwrap().write(&record(20)).unwrap();
+            assert_eq!(read(&path).unwrap(), vec![record(10), record(20)]);
+        }
+
+        /// A partial record left by a killed recorder gets overwritten
+        #[test]
+        fn reopen_truncated() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("trace.bin");
+            Writer::append(&path).unwrap().write(&record(10)).unwrap();
+            let mut bytes = fs::read(&path).unwrap();
+            bytes.extend_from_slice(&encode(&record(15))[..7]);
+            fs::write(&path, bytes).unwrap();
+            Writer::append(&path).unwrap().write(&record(20)).unwrap();
+            assert_eq!(read(&path).unwrap(), vec![record(10), record(20)]);
+        }
+
+        #[test]
+        fn not_a_trace() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("trace.bin");
+            fs::write(&path, b"Hello, world!").unwrap();
+            let e = Writer::append(&path).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    mod parse {
+        use super::*;
+
+        fn trace(records: &[Record]) -> Vec<u8> {
+            let mut bytes = encode(&Header {
+                magic:   MAGIC,
+                version: VERSION,
+            });
+            for record in records {
+                bytes.extend(encode(record));
+            }
+            bytes
+        }
+
+        #[test]
+        fn empty() {
+            let bytes = trace(&[]);
+            assert_eq!(parse(&bytes).unwrap(), (vec![], bytes.len()));
+        }
+
+        #[test]
+        fn truncated() {
+            let full = trace(&[record(10), record(20)]);
+            let one = trace(&[record(10)]);
+            let (records, len) = parse(&full[..full.len() - 1]).unwrap();
+            assert_eq!(records, vec![record(10)]);
+            assert_eq!(len, one.len());
+        }
+
+        #[test]
+        fn wrong_magic() {
+            let mut bytes = trace(&[record(10)]);
+            bytes[0] = b'X';
+            let e = parse(&bytes).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+
+        #[test]
+        fn wrong_version() {
+            let mut bytes = trace(&[record(10)]);
+            bytes[8] = 99;
+            let e = parse(&bytes).unwrap_err();
+            assert_eq!(e.to_string(), "unsupported trace version 99");
+        }
+    }
+}
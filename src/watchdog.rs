@@ -0,0 +1,123 @@
+//! Detects when nfsd stops completing operations.
+use std::time::{Duration, Instant};
+
+/// The result of one watchdog sample
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Status {
+    /// Time since nfsd last completed an operation, while operations were in
+    /// flight.  Zero if nfsd is idle.
+    pub since_last_completion: Duration,
+    /// Has `since_last_completion` crossed the threshold?
+    pub stalled:               bool,
+}
+
+/// Tracks nfsd's progress across samples
+#[derive(Debug)]
+pub struct Watchdog {
+    threshold:     Duration,
+    last_donecnt:  u64,
+    last_progress: Instant,
+    stalled:       bool,
+}
+
+impl Watchdog {
+    /// Create a new watchdog that will report a stall once nfsd goes
+    /// `threshold` without completing an operation.
+    pub fn new(threshold: Duration, now: Instant) -> Self {
+        Watchdog {
+            threshold,
+            last_donecnt: 0,
+            last_progress: now,
+            stalled: false,
+        }
+    }
+
+    /// Process a new sample of nfsd's counters, taken at time `now`.
+    pub fn sample(
+        &mut self,
+        donecnt: u64,
+        in_flight: u64,
+        now: Instant,
+    ) -> Status {
+        if donecnt != self.last_donecnt || in_flight == 0 {
+            self.last_donecnt = donecnt;
+            self.last_progress = now;
+        }
+        let since_last_completion =
+            now.saturating_duration_since(self.last_progress);
+        let stalled = since_last_completion >= self.threshold;
+        if stalled && !self.stalled {
+            log::warn!(
+                "nfsd has not completed an operation in {:.0?}, with {} in \
+                 flight",
+                since_last_completion,
+                in_flight
+            );
+        } else if !stalled && self.stalled {
+            log::info!("nfsd is completing operations again");
+        }
+        self.stalled = stalled;
+        Status {
+            since_last_completion,
+            stalled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_secs(60);
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    /// An idle nfsd is never stalled
+    #[test]
+    fn idle() {
+        let t0 = Instant::now();
+        let mut wd = Watchdog::new(THRESHOLD, t0);
+        for i in 0..10 {
+            let status = wd.sample(1000, 0, t0 + secs(30 * i));
+            assert_eq!(status.since_last_completion, Duration::ZERO);
+            assert!(!status.stalled);
+        }
+    }
+
+    /// A busy nfsd that keeps completing operations is not stalled
+    #[test]
+    fn busy() {
+        let t0 = Instant::now();
+        let mut wd = Watchdog::new(THRESHOLD, t0);
+        for i in 0..10 {
+            let status = wd.sample(1000 + i, 5, t0 + secs(30 * i));
+            assert_eq!(status.since_last_completion, Duration::ZERO);
+            assert!(!status.stalled);
+        }
+    }
+
+    #[test]
+    fn stall_and_recover() {
+        let t0 = Instant::now();
+        let mut wd = Watchdog::new(THRESHOLD, t0);
+        wd.sample(1000, 1, t0);
+
+        let status = wd.sample(1000, 3, t0 + secs(30));
+        assert_eq!(status.since_last_completion, secs(30));
+        assert!(!status.stalled);
+
+        let status = wd.sample(1000, 8, t0 + secs(60));
+        assert_eq!(status.since_last_completion, secs(60));
+        assert!(status.stalled);
+
+        let status = wd.sample(1000, 12, t0 + secs(90));
+        assert_eq!(status.since_last_completion, secs(90));
+        assert!(status.stalled);
+
+        let status = wd.sample(1001, 11, t0 + secs(120));
+        assert_eq!(status.since_last_completion, Duration::ZERO);
+        assert!(!status.stalled);
+    }
+}
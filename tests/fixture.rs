@@ -0,0 +1,340 @@
+// vim: tw=80
+//! End-to-end tests of the exporter, reading statistics from fixture files
+//! and traces.
+
+use std::{
+    ffi::OsStr,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+#[cfg(target_pointer_width = "64")]
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[path = "../src/ffi64.rs"]
+mod ffi;
+#[cfg(target_pointer_width = "32")]
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[path = "../src/ffi32.rs"]
+mod ffi;
+
+/// A running exporter, killed on drop
+struct Exporter {
+    child: Child,
+    port:  u16,
+}
+
+impl Exporter {
+    fn start(fixture: &Path) -> Self {
+        Self::spawn(&["--fixture".as_ref(), fixture.as_os_str()])
+    }
+
+    fn replay(trace: &Path, speed: &str) -> Self {
+        Self::spawn(&[
+            "replay".as_ref(),
+            trace.as_os_str(),
+            "--speed".as_ref(),
+            speed.as_ref(),
+        ])
+    }
+
+    fn spawn(args: &[&OsStr]) -> Self {
+        // Find an unused port
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_nfs-exporter"))
+            .args(["-b", "127.0.0.1", "-p", &port.to_string()])
+            .args(args)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let exporter = Exporter { child, port };
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return exporter;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("exporter did not start");
+    }
+
+    /// Fetch /metrics, returning the status line and body
+    fn scrape(&self) -> (String, String) {
+        let (head, body) = self.scrape_accepting("*/*");
+        let status = head.lines().next().unwrap().to_string();
+        (status, body)
+    }
+
+    /// Fetch /metrics with the given Accept header, returning the response's
+    /// head and body
+    fn scrape_accepting(&self, accept: &str) -> (String, String) {
+        let (head, body) = self.scrape_bytes(accept);
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    fn scrape_bytes(&self, accept: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        write!(
+            stream,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: \
+             {accept}\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(response[..end].to_vec()).unwrap();
+        (head, response[end + 4..].to_vec())
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A raw image of the kernel's stats, as the kernel would write it
+fn image_of(raw: &ffi::nfsstatsv1) -> &[u8] {
+    let p = (raw as *const ffi::nfsstatsv1).cast::<u8>();
+    let len = std::mem::size_of::<ffi::nfsstatsv1>();
+    unsafe { std::slice::from_raw_parts(p, len) }
+}
+
+fn write_image(path: &Path, raw: &ffi::nfsstatsv1) {
+    std::fs::write(path, image_of(raw)).unwrap();
+}
+
+/// Write a trace, as `nfs-exporter record` would.  Each record is a timestamp
+/// in seconds, and the kernel's stats at that time.
+fn write_trace(path: &Path, records: &[(u64, ffi::nfsstatsv1)]) {
+    let mut trace = b"NFSTRACE".to_vec();
+    trace.extend(1u32.to_le_bytes());
+    for (secs, raw) in records {
+        let image = image_of(raw);
+        trace.extend((secs * 1_000_000_000).to_le_bytes());
+        trace.extend((image.len() as u64).to_le_bytes());
+        trace.extend(image);
+        // osreldate: Some(1400000)
+        trace.push(1);
+        trace.extend(1_400_000u64.to_le_bytes());
+        // uptime_ns
+        trace.extend((secs * 1_000_000_000).to_le_bytes());
+    }
+    std::fs::write(path, trace).unwrap();
+}
+
+fn nfsstats() -> ffi::nfsstatsv1 {
+    let mut raw = ffi::nfsstatsv1 {
+        vers: ffi::NFSSTATS_V1 as i32,
+        srvstartcnt: 1000,
+        srvdonecnt: 1000,
+        busytime: ffi::bintime {
+            sec:  3,
+            frac: 1 << 63,
+        },
+        rpcrequests: 77,
+        ..Default::default()
+    };
+    raw.srvrpccnt[ffi::NFSV4OP_READ as usize] = 42;
+    raw.srvrpccnt[ffi::NFSV4OP_COPY as usize] = 5;
+    raw
+}
+
+#[test]
+fn scrape() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    write_image(&path, &nfsstats());
+    let exporter = Exporter::start(&path);
+
+    let (status, body) = exporter.scrape();
+    assert!(status.contains("200"), "{status}");
+    assert!(body.contains("\nnfs_up 1\n"));
+    assert!(body.contains("# TYPE nfs_nfsd_requests_total counter\n"));
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Read\"} 42\n"));
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Copy\"} 5\n"));
+    assert!(body.contains("\nnfs_nfsd_busy_seconds_total 3.5\n"));
+    assert!(body.contains("\nnfs_client_rpcs 77\n"));
+    let struct_version = concat!(
+        "nfs_exporter_kernel_struct_version",
+        "{layout=\"NFSSTATS_V1\",osreldate=\"\",vers=\"2\"} 1\n"
+    );
+    assert!(body.contains(struct_version));
+}
+
+/// A bare image doesn't say when it was taken, so there's no telling how long
+/// it has been since nfsd made progress
+#[test]
+fn progress_unknown() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    let mut raw = nfsstats();
+    raw.srvstartcnt = 1002;
+    raw.busyfrom = ffi::bintime { sec: 1, frac: 0 };
+    write_image(&path, &raw);
+    let exporter = Exporter::start(&path);
+
+    let (_, body) = exporter.scrape();
+    assert!(body.contains("\nnfs_nfsd_ops_in_flight 2\n"));
+    assert!(!body.contains("nfs_nfsd_seconds_since_progress"));
+}
+
+/// The v2 schema publishes the renamed metrics instead of the v1 ones
+#[test]
+fn metric_schema_v2() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    write_image(&path, &nfsstats());
+    let exporter = Exporter::spawn(&[
+        "--fixture".as_ref(),
+        path.as_os_str(),
+        "--metric-schema".as_ref(),
+        "v2".as_ref(),
+    ]);
+
+    let (_, body) = exporter.scrape();
+    assert!(body.contains("\nnfs_nfsd_operations_completed_total 1000\n"));
+    assert!(body.contains("\nnfs_client_rpcs_total 77\n"));
+    assert!(!body.contains("nfs_nfsd_done_count"));
+    assert!(!body.contains("nfs_nfsd_busytime"));
+}
+
+/// Clients that ask for OpenMetrics get it
+#[test]
+fn openmetrics() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    write_image(&path, &nfsstats());
+    let exporter = Exporter::start(&path);
+
+    let accept = "application/openmetrics-text;version=1.0.0,text/plain;q=0.5";
+    let (head, body) = exporter.scrape_accepting(accept);
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("content-type: application/openmetrics-text"));
+    assert!(body.ends_with("\n# EOF\n"), "{body}");
+    assert!(body.contains("# TYPE nfs_nfsd_requests counter\n"));
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Read\"} 42\n"));
+    assert!(body.contains("nfs_nfsd_requests_created{method=\"Read\"} "));
+    assert!(body.contains("# UNIT nfs_nfsd_busy_seconds seconds\n"));
+
+    let (head, body) = exporter.scrape_accepting("text/plain");
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("content-type: text/plain; version=0.0.4"));
+    assert!(!body.contains("# EOF"));
+}
+
+/// Clients that ask for delimited protobuf get it
+#[test]
+fn protobuf() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    write_image(&path, &nfsstats());
+    let exporter = Exporter::start(&path);
+
+    let accept = concat!(
+        "application/vnd.google.protobuf;",
+        "proto=io.prometheus.client.MetricFamily;encoding=delimited,",
+        "text/plain;q=0.5"
+    );
+    let (head, body) = exporter.scrape_bytes(accept);
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains(
+        "content-type: application/vnd.google.protobuf; \
+         proto=io.prometheus.client.metricfamily; encoding=delimited"
+    ));
+    // Each message is prefixed by its length, as a varint
+    let mut len = 0;
+    let mut pos = 0;
+    for (i, b) in body.iter().enumerate() {
+        len |= usize::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            pos = i + 1;
+            break;
+        }
+    }
+    assert!(len > 0 && pos + len <= body.len());
+    assert!(
+        body.windows(b"nfs_nfsd_requests_total".len())
+            .any(|w| w == b"nfs_nfsd_requests_total")
+    );
+}
+
+/// Counters that go backwards between scrapes are detected as a reset
+#[test]
+fn reset() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    write_image(&path, &nfsstats());
+    let exporter = Exporter::start(&path);
+    let (_, body) = exporter.scrape();
+    assert!(body.contains("\nnfs_nfsd_counter_resets_total 0\n"));
+
+    let mut raw = nfsstats();
+    raw.srvstartcnt = 10;
+    raw.srvdonecnt = 10;
+    raw.srvrpccnt[ffi::NFSV4OP_READ as usize] = 1;
+    write_image(&path, &raw);
+    let (_, body) = exporter.scrape();
+    assert!(body.contains("\nnfs_nfsd_counter_resets_total 1\n"));
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Read\"} 1\n"));
+}
+
+/// A collection failure is reported in the metrics, not as an HTTP error
+#[test]
+fn missing_fixture() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nfsstats");
+    let exporter = Exporter::start(&path);
+
+    let (status, body) = exporter.scrape();
+    assert!(status.contains("200"), "{status}");
+    assert!(body.contains("\nnfs_up 0\n"));
+    assert!(body.contains("nfs_collect_errors_total{errno=\"2\"} 1\n"));
+}
+
+fn trace() -> Vec<(u64, ffi::nfsstatsv1)> {
+    let mut later = nfsstats();
+    later.srvrpccnt[ffi::NFSV4OP_READ as usize] = 142;
+    vec![(1_000_000, nfsstats()), (1_003_600, later)]
+}
+
+/// Replay starts at the beginning of the trace
+#[test]
+fn replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.bin");
+    write_trace(&path, &trace());
+    let exporter = Exporter::replay(&path, "1");
+
+    let (status, body) = exporter.scrape();
+    assert!(status.contains("200"), "{status}");
+    assert!(body.contains("\nnfs_up 1\n"));
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Read\"} 42\n"));
+    let struct_version = concat!(
+        "nfs_exporter_kernel_struct_version",
+        "{layout=\"NFSSTATS_V1\",osreldate=\"1400000\",vers=\"2\"} 1\n"
+    );
+    assert!(body.contains(struct_version));
+}
+
+/// An accelerated replay quickly reaches the end of the trace, and stays there
+#[test]
+fn replay_accelerated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.bin");
+    write_trace(&path, &trace());
+    let exporter = Exporter::replay(&path, "1e9");
+
+    let (_, body) = exporter.scrape();
+    assert!(body.contains("nfs_nfsd_requests_total{method=\"Read\"} 142\n"));
+}